@@ -0,0 +1,295 @@
+//! Asynchronous printing, for applications (e.g. a POS backend) that print from async tasks.
+//!
+//! There is no async USB transport: [`Blocking`] runs the I/O of a (blocking) [`Driver`] on
+//! tokio's blocking thread pool, so each read or write still holds a pool thread for as long as
+//! it takes, though never a worker thread. The wrapped driver behaves as it does with
+//! [`crate::printer::Printer`], e.g. [`crate::printer::UsbDriver`] retries as its
+//! [`crate::printer::RetryPolicy`] allows.
+//!
+//! [`AsyncPrinter`] initializes the printer and queries its status (as configured by
+//! [`PrinterConfig::status_method`]) with the same commands as `Printer`, but only mirrors its
+//! basic printing methods.
+
+use std::future::Future;
+
+use tokio::{task, time::sleep};
+use tracing::debug;
+
+use crate::escpos::{PrinterStatus, RT_STATUS_BUF_LEN};
+use crate::printer::{
+    Driver, DriverKind, EscposMarkdown, LOG_CMD_MAX_LEN, PrinterConfig, PrintyError, PrintyResult,
+    StatusQuery,
+};
+
+pub trait AsyncDriver {
+    fn read(&mut self, buf: &mut [u8]) -> impl Future<Output = PrintyResult<usize>> + Send;
+
+    fn write(&mut self, data: &[u8]) -> impl Future<Output = PrintyResult<usize>> + Send;
+
+    fn drain(&mut self) -> impl Future<Output = PrintyResult<()>> + Send;
+
+    /// See [`Driver::flush`]
+    fn flush(&mut self) -> impl Future<Output = PrintyResult<()>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Transport of the driver, for diagnostics
+    fn kind(&self) -> DriverKind;
+}
+
+/// Adapts a (blocking) [`Driver`] into an [`AsyncDriver`] by running each I/O operation on
+/// tokio's blocking thread pool, so that the calling task's worker thread is never blocked.
+pub struct Blocking<D> {
+    kind: DriverKind,
+    // Moved into the blocking task for the duration of each I/O operation
+    driver: Option<D>,
+}
+
+impl<D: Driver + Send + 'static> Blocking<D> {
+    pub fn new(driver: D) -> Self {
+        Self {
+            kind: driver.kind(),
+            driver: Some(driver),
+        }
+    }
+
+    async fn run<F, T>(&mut self, io_func: F) -> PrintyResult<T>
+    where
+        F: FnOnce(&mut D) -> PrintyResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let mut driver = self.driver.take().ok_or(PrintyError::Driver {
+            kind: self.kind,
+            context: "Driver was lost by a previously cancelled I/O operation".to_string(),
+            source: None,
+        })?;
+
+        let (driver, result) = task::spawn_blocking(move || {
+            let result = io_func(&mut driver);
+            (driver, result)
+        })
+        .await
+        .map_err(|e| PrintyError::Driver {
+            kind: self.kind,
+            context: "Blocking I/O task failed".to_string(),
+            source: Some(Box::new(e)),
+        })?;
+
+        self.driver = Some(driver);
+        result
+    }
+}
+
+impl<D: Driver + Send + 'static> AsyncDriver for Blocking<D> {
+    async fn read(&mut self, buf: &mut [u8]) -> PrintyResult<usize> {
+        let buf_len = buf.len();
+        let (r_len, r_buf) = self
+            .run(move |driver| {
+                let mut r_buf = vec![0u8; buf_len];
+                driver.read(&mut r_buf).map(|r_len| (r_len, r_buf))
+            })
+            .await?;
+        buf[..r_len].copy_from_slice(&r_buf[..r_len]);
+        Ok(r_len)
+    }
+
+    async fn write(&mut self, data: &[u8]) -> PrintyResult<usize> {
+        let data = data.to_vec();
+        self.run(move |driver| driver.write(&data)).await
+    }
+
+    async fn drain(&mut self) -> PrintyResult<()> {
+        self.run(|driver| driver.drain()).await
+    }
+
+    async fn flush(&mut self) -> PrintyResult<()> {
+        self.run(|driver| driver.flush()).await
+    }
+
+    fn kind(&self) -> DriverKind {
+        self.kind
+    }
+}
+
+/// Asynchronous counterpart of [`crate::printer::Printer`]
+pub struct AsyncPrinter<D> {
+    pub driver: D,
+    pub config: PrinterConfig,
+    bytes_written: usize,
+}
+
+impl<D: AsyncDriver> AsyncPrinter<D> {
+    pub async fn new(driver: D, config: PrinterConfig) -> PrintyResult<Self> {
+        let mut printer = AsyncPrinter {
+            driver,
+            config,
+            bytes_written: 0,
+        };
+        if !printer.config.skip_init {
+            printer.init().await?;
+        }
+        Ok(printer)
+    }
+
+    // See `Printer::init` for details on the initialization sequence
+    async fn init(&mut self) -> PrintyResult<&Self> {
+        if self.config.drain_on_init {
+            self.driver.drain().await?;
+        }
+        let init_sequence = self.config.init_sequence();
+        self.write(&init_sequence).await?;
+        Ok(self)
+    }
+
+    async fn write(&mut self, data: &[u8]) -> PrintyResult<usize> {
+        debug!(
+            "Sending {} bytes ({}): {:02x?}",
+            data.len(),
+            self.driver.kind().name(),
+            &data[..data.len().min(LOG_CMD_MAX_LEN)]
+        );
+        let w_len = self.driver.write(data).await?;
+        self.bytes_written += w_len;
+        Ok(w_len)
+    }
+
+    pub fn bytes_written(&self) -> usize {
+        self.bytes_written
+    }
+
+    /// See [`crate::printer::Printer::status`]
+    pub async fn status(&mut self) -> Option<PrinterStatus> {
+        self.driver.flush().await.ok()?;
+        let status = self.query_status().await;
+        if status.is_none() {
+            debug!("No valid status reply ({:?})", self.config.status_method);
+        }
+        status
+    }
+
+    async fn query_status(&mut self) -> Option<PrinterStatus> {
+        let mut query = StatusQuery::new(&self.config);
+        while let Some((request, reply_delay)) = query.next_request() {
+            self.write(&request).await.ok()?;
+
+            sleep(reply_delay).await;
+
+            let mut buf = [0u8; RT_STATUS_BUF_LEN];
+            let len = self.driver.read(&mut buf).await.ok()?;
+            query.reply(&buf[..len])?;
+        }
+        query.status()
+    }
+
+    pub async fn cut(&mut self) -> PrintyResult<&mut Self> {
+        let cut_cmd = self.config.cut_sequence();
+        self.write(&cut_cmd).await?;
+        Ok(self)
+    }
+
+    pub async fn print(&mut self, data: &str) -> PrintyResult<&mut Self> {
+        if data.trim().is_empty() {
+            return Ok(self);
+        }
+
+        let text = self.config.prepare_text(data).into_owned();
+        self.write(text.as_bytes()).await?;
+        Ok(self)
+    }
+
+    pub async fn print_md(&mut self, data: &str) -> PrintyResult<&mut Self> {
+        if data.trim().is_empty() {
+            return Ok(self);
+        }
+
+        let compiled = EscposMarkdown::from(&self.config).compile(data)?;
+        self.write(&compiled).await?;
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::escpos::{
+        CMD_DISABLE_ASB, CMD_INIT, CMD_TRANSMIT_STATUS, PaperStatus, TransmitStatusReq,
+    };
+    use crate::printer::StatusMethod;
+
+    // Driver of a scripted printer, which reads back the queued replies in order
+    #[derive(Default)]
+    struct MockDriver {
+        written: Vec<u8>,
+        replies: VecDeque<Vec<u8>>,
+    }
+
+    impl AsyncDriver for MockDriver {
+        async fn read(&mut self, buf: &mut [u8]) -> PrintyResult<usize> {
+            let Some(reply) = self.replies.pop_front() else {
+                return Ok(0);
+            };
+            buf[..reply.len()].copy_from_slice(&reply);
+            Ok(reply.len())
+        }
+
+        async fn write(&mut self, data: &[u8]) -> PrintyResult<usize> {
+            self.written.extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        async fn drain(&mut self) -> PrintyResult<()> {
+            Ok(())
+        }
+
+        fn kind(&self) -> DriverKind {
+            DriverKind::Debug
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn init_sends_the_init_sequence() {
+        let printer = block_on(AsyncPrinter::new(
+            MockDriver::default(),
+            PrinterConfig::default(),
+        ))
+        .unwrap();
+        assert_eq!(printer.driver.written, [CMD_INIT, CMD_DISABLE_ASB].concat());
+    }
+
+    #[test]
+    fn status_follows_the_status_method() {
+        let config = PrinterConfig {
+            skip_init: true,
+            status_method: StatusMethod::GsR,
+            ..PrinterConfig::default()
+        };
+        let mut printer = block_on(AsyncPrinter::new(MockDriver::default(), config)).unwrap();
+        printer.driver.replies.push_back(vec![0b0000_0011]);
+        let status = block_on(printer.status()).unwrap();
+        assert_eq!(
+            printer.driver.written,
+            CMD_TRANSMIT_STATUS(TransmitStatusReq::PaperSensor)
+        );
+        assert_eq!(*status.paper_status(), PaperStatus::NearEnd);
+    }
+
+    #[test]
+    fn status_fails_without_reply() {
+        let config = PrinterConfig {
+            skip_init: true,
+            ..PrinterConfig::default()
+        };
+        let mut printer = block_on(AsyncPrinter::new(MockDriver::default(), config)).unwrap();
+        assert!(block_on(printer.status()).is_none());
+    }
+}
@@ -0,0 +1,232 @@
+//! Transcoding of text into the single-byte code pages of the printer, which interprets bytes
+//! 0x80-0xFF according to the code page selected by `ESC t`.
+
+use std::fmt::Display;
+
+/// Code pages that text can be transcoded into, in order of preference when detecting one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CodePage {
+    Cp437,
+    Cp850,
+    Cp1252,
+}
+
+/// How text is encoded before being sent to the printer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Encoding {
+    /// Send text as UTF-8, leaving any non-ASCII characters to the printer's current code page
+    Utf8,
+    Cp437,
+    Cp850,
+    Cp1252,
+    /// Pick the first code page that covers all characters of the text
+    Auto,
+}
+
+/// What is printed in place of characters that the code page lacks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GlyphFallback {
+    /// A `?`
+    Question,
+    /// Nothing, leaving them out
+    Skip,
+    /// An inline image of the character, drawn with a font (see [`crate::glyph`]), or a `?` if
+    /// the font lacks it too
+    Raster,
+}
+
+impl CodePage {
+    const ALL: [CodePage; 3] = [CodePage::Cp437, CodePage::Cp850, CodePage::Cp1252];
+
+    /// Number of the code page for `ESC t` on Epson printers
+    pub fn escpos_page(&self) -> u8 {
+        match self {
+            CodePage::Cp437 => 0,
+            CodePage::Cp850 => 2,
+            CodePage::Cp1252 => 16,
+        }
+    }
+
+    fn high_half(&self) -> &'static [char; 128] {
+        match self {
+            CodePage::Cp437 => &CP437,
+            CodePage::Cp850 => &CP850,
+            CodePage::Cp1252 => &CP1252,
+        }
+    }
+
+    fn encode_char(&self, ch: char) -> Option<u8> {
+        if ch.is_ascii() {
+            return Some(ch as u8);
+        }
+        self.high_half()
+            .iter()
+            .position(|&c| c == ch)
+            .map(|idx| 0x80 + idx as u8)
+    }
+
+    /// Encodes `text`, replacing characters the code page lacks with `?`
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        self.encode_with(text, |_, buf| buf.push(b'?'))
+    }
+
+    /// Encodes `text`, letting `fallback` append the bytes for each character the code page lacks
+    pub fn encode_with(&self, text: &str, mut fallback: impl FnMut(char, &mut Vec<u8>)) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(text.len());
+        for ch in text.chars() {
+            match self.encode_char(ch) {
+                Some(byte) => buf.push(byte),
+                None => fallback(ch, &mut buf),
+            }
+        }
+        buf
+    }
+
+    /// Decodes text encoded in the code page, replacing undefined bytes with `U+FFFD`
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        bytes
+            .iter()
+            .map(|&b| match b {
+                0x00..0x80 => b as char,
+                _ => match self.high_half()[(b - 0x80) as usize] {
+                    '\0' => char::REPLACEMENT_CHARACTER,
+                    ch => ch,
+                },
+            })
+            .collect()
+    }
+
+    /// Picks the first code page that covers all characters of `text`, or else the one that
+    /// covers the most of them
+    pub fn detect(text: &str) -> CodePage {
+        let non_ascii = text.chars().filter(|ch| !ch.is_ascii()).collect::<Vec<_>>();
+        let uncovered = |page: &CodePage| {
+            non_ascii
+                .iter()
+                .filter(|&&ch| page.encode_char(ch).is_none())
+                .count()
+        };
+        // `min_by_key` keeps the first of equally good code pages
+        Self::ALL
+            .into_iter()
+            .min_by_key(uncovered)
+            .unwrap_or(CodePage::Cp437)
+    }
+}
+
+impl Display for CodePage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodePage::Cp437 => write!(f, "CP437"),
+            CodePage::Cp850 => write!(f, "CP850"),
+            CodePage::Cp1252 => write!(f, "CP1252"),
+        }
+    }
+}
+
+impl Encoding {
+    /// Resolves the code page to transcode `text` into, if any
+    pub fn code_page(&self, text: &str) -> Option<CodePage> {
+        match self {
+            Encoding::Utf8 => None,
+            Encoding::Cp437 => Some(CodePage::Cp437),
+            Encoding::Cp850 => Some(CodePage::Cp850),
+            Encoding::Cp1252 => Some(CodePage::Cp1252),
+            Encoding::Auto => Some(CodePage::detect(text)),
+        }
+    }
+}
+
+// Characters of bytes 0x80-0xFF, with `\0` marking bytes that are undefined in the code page
+
+const CP437: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', 'É', 'æ', 'Æ',
+    'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', 'á', 'í', 'ó', 'ú', 'ñ', 'Ñ',
+    'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕',
+    '╣', '║', '╗', '╝', '╜', '╛', '┐', '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦',
+    '╠', '═', '╬', '╧', '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐',
+    '▀', 'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', '≡', '±',
+    '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{00A0}',
+];
+
+const CP850: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', 'É', 'æ', 'Æ',
+    'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', 'ø', '£', 'Ø', '×', 'ƒ', 'á', 'í', 'ó', 'ú', 'ñ', 'Ñ',
+    'ª', 'º', '¿', '®', '¬', '½', '¼', '¡', '«', '»', '░', '▒', '▓', '│', '┤', 'Á', 'Â', 'À', '©',
+    '╣', '║', '╗', '╝', '¢', '¥', '┐', '└', '┴', '┬', '├', '─', '┼', 'ã', 'Ã', '╚', '╔', '╩', '╦',
+    '╠', '═', '╬', '¤', 'ð', 'Ð', 'Ê', 'Ë', 'È', 'ı', 'Í', 'Î', 'Ï', '┘', '┌', '█', '▄', '¦', 'Ì',
+    '▀', 'Ó', 'ß', 'Ô', 'Ò', 'õ', 'Õ', 'µ', 'þ', 'Þ', 'Ú', 'Û', 'Ù', 'ý', 'Ý', '¯', '´',
+    '\u{00AD}', '±', '‗', '¾', '¶', '§', '÷', '¸', '°', '¨', '·', '¹', '³', '²', '■', '\u{00A0}',
+];
+
+const CP1252: [char; 128] = [
+    '€', '\0', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\0', 'Ž', '\0', '\0', '‘',
+    '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\0', 'ž', 'Ÿ', '\u{00A0}', '¡', '¢',
+    '£', '¤', '¥', '¦', '§', '¨', '©', 'ª', '«', '¬', '\u{00AD}', '®', '¯', '°', '±', '²', '³',
+    '´', 'µ', '¶', '·', '¸', '¹', 'º', '»', '¼', '½', '¾', '¿', 'À', 'Á', 'Â', 'Ã', 'Ä', 'Å', 'Æ',
+    'Ç', 'È', 'É', 'Ê', 'Ë', 'Ì', 'Í', 'Î', 'Ï', 'Ð', 'Ñ', 'Ò', 'Ó', 'Ô', 'Õ', 'Ö', '×', 'Ø', 'Ù',
+    'Ú', 'Û', 'Ü', 'Ý', 'Þ', 'ß', 'à', 'á', 'â', 'ã', 'ä', 'å', 'æ', 'ç', 'è', 'é', 'ê', 'ë', 'ì',
+    'í', 'î', 'ï', 'ð', 'ñ', 'ò', 'ó', 'ô', 'õ', 'ö', '÷', 'ø', 'ù', 'ú', 'û', 'ü', 'ý', 'þ', 'ÿ',
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_pages_round_trip_every_defined_byte() {
+        for page in CodePage::ALL {
+            for byte in 0x00..=0xFF {
+                let decoded = page.decode(&[byte]);
+                if decoded == char::REPLACEMENT_CHARACTER.to_string() {
+                    assert_eq!(page, CodePage::Cp1252, "{page} lacks byte {byte:#04x}");
+                    continue;
+                }
+                assert_eq!(page.encode(&decoded), [byte], "{page} byte {byte:#04x}");
+            }
+        }
+    }
+
+    #[test]
+    fn code_pages_map_known_characters() {
+        let known = [
+            (
+                CodePage::Cp437,
+                "Ç¢ßñ░█■",
+                [0x80, 0x9B, 0xE1, 0xA4, 0xB0, 0xDB, 0xFE],
+            ),
+            (
+                CodePage::Cp850,
+                "Çøßñ░Ø■",
+                [0x80, 0x9B, 0xE1, 0xA4, 0xB0, 0x9D, 0xFE],
+            ),
+            (
+                CodePage::Cp1252,
+                "€“ßñ©Øÿ",
+                [0x80, 0x93, 0xDF, 0xF1, 0xA9, 0xD8, 0xFF],
+            ),
+        ];
+        for (page, text, bytes) in known {
+            assert_eq!(page.encode(text), bytes, "{page}");
+            assert_eq!(page.decode(&bytes), text, "{page}");
+        }
+    }
+
+    #[test]
+    fn undefined_and_missing_characters() {
+        assert_eq!(CodePage::Cp1252.decode(&[0x81]), "\u{FFFD}");
+        assert_eq!(CodePage::Cp437.encode("€1"), b"?1");
+        assert_eq!(
+            CodePage::Cp850.encode_with("a€b", |_, buf| buf.extend(b"EUR")),
+            b"aEURb"
+        );
+    }
+
+    #[test]
+    fn detect_picks_the_first_covering_code_page() {
+        assert_eq!(CodePage::detect("Grüße"), CodePage::Cp437);
+        assert_eq!(CodePage::detect("Ø"), CodePage::Cp850);
+        assert_eq!(CodePage::detect("5€"), CodePage::Cp1252);
+        assert_eq!(CodePage::detect("plain"), CodePage::Cp437);
+    }
+}
@@ -0,0 +1,414 @@
+//! `ESC/POS` command builders that write into a caller-provided buffer instead of allocating,
+//! for driving the same printers without `std` or `alloc` (e.g. from a microcontroller). Only
+//! `core` is used here, so the module can be lifted into a `no_std` crate as-is.
+//!
+//! Each builder writes its command into the start of `buf` and returns the number of bytes
+//! written, or `None` if `buf` is too small (or the payload too large for the command).
+//! Parameters are the values that the command encodes, e.g. `49` or `50` for the QR code model,
+//! which [`crate::escpos`] wraps in typed commands returning a `Vec`.
+
+pub(crate) const ESC: u8 = 0x1B;
+pub(crate) const DLE: u8 = 0x10;
+pub(crate) const EOT: u8 = 0x04;
+pub(crate) const GS: u8 = 0x1D;
+pub(crate) const DC4: u8 = 0x14;
+
+// Defines a command made of a constant header and one byte per parameter
+macro_rules! def_cmd_into {
+    ($(#[$attr:meta])* $fn_name:ident, $header:expr, $( $param_name:ident : $param_type:ty ),+) => {
+        $(#[$attr])*
+        #[allow(non_snake_case)]
+        pub fn $fn_name(buf: &mut [u8], $( $param_name: $param_type ),+) -> Option<usize> {
+            write_cmd(buf, $header, &[$($param_name as u8),+])
+        }
+    };
+}
+
+/// Writes `header` followed by `params` into the start of `buf`, returning the number of bytes
+/// written, or `None` if `buf` is too small
+pub fn write_cmd(buf: &mut [u8], header: &[u8], params: &[u8]) -> Option<usize> {
+    let len = header.len() + params.len();
+    let (header_buf, params_buf) = buf.get_mut(..len)?.split_at_mut(header.len());
+    header_buf.copy_from_slice(header);
+    params_buf.copy_from_slice(params);
+    Some(len)
+}
+
+def_cmd_into!(
+    /// `DLE EOT n`: transmits the real-time status of type `n` (1-4), executed as soon as it is
+    /// received
+    CMD_RT_STATUS_INTO,
+    &[DLE, EOT],
+    n: u8
+);
+
+def_cmd_into!(
+    /// `GS r n`: transmits the paper sensor (1) or drawer (2) status, once the printer reaches
+    /// the command in its receive buffer
+    CMD_TRANSMIT_STATUS_INTO,
+    &[GS, b'r'],
+    n: u8
+);
+
+def_cmd_into!(
+    /// `GS I n`: transmits the printer ID of type `n`, e.g. the model ID (1) or the firmware
+    /// version (65)
+    CMD_IDENTITY_INTO,
+    &[GS, b'I'],
+    n: u8
+);
+
+def_cmd_into!(
+    /// `GS V 66 n`: feeds paper `n` vertical motion units past the cutting position and cuts,
+    /// to leave a margin below the last printed line
+    CMD_CUT_FEED_INTO,
+    &[GS, b'V', 66],
+    units: u8
+);
+
+def_cmd_into!(
+    /// `ESC t n`: selects the character code table (code page) for bytes 0x80-0xFF, e.g. 0 for
+    /// PC437 and 2 for PC850. The table numbers are vendor specific beyond the first few
+    CMD_CODEPAGE_INTO,
+    &[ESC, b't'],
+    page: u8
+);
+
+def_cmd_into!(
+    /// `ESC V`: turns characters 90° clockwise in place. Lines still run across the paper and
+    /// advance down it, so with the paper turned for the characters to be upright, each line
+    /// reads as a column (bottom to top) and lines follow each other from left to right.
+    /// Graphics, QR codes and underlines are not rotated
+    CMD_ROTATE_90_INTO,
+    &[ESC, b'V'],
+    enable: bool
+);
+
+def_cmd_into!(
+    /// `ESC M`: selects Font B (9x17 dots on the TM-T88IV) instead of the default Font A (12x24
+    /// dots)
+    CMD_FONT_B_INTO,
+    &[ESC, b'M'],
+    enable: bool
+);
+
+def_cmd_into!(
+    /// `ESC E`: turns emphasized (bold) mode on or off
+    CMD_BOLD_INTO,
+    &[ESC, b'E'],
+    enable: bool
+);
+
+def_cmd_into!(
+    /// `ESC -`: turns (1-dot thick) underlining on or off
+    CMD_UNDERLINE_INTO,
+    &[ESC, b'-'],
+    enable: bool
+);
+
+def_cmd_into!(
+    /// `GS B`: turns white on black (reverse) printing on or off
+    CMD_REVERSE_INTO,
+    &[GS, b'B'],
+    enable: bool
+);
+
+/// `GS !`: magnifies characters `h_magnify` times in height and `w_magnify` times in width
+/// (1-8), which is encoded as `magnify - 1` in the low and high nibble respectively
+#[allow(non_snake_case)]
+pub fn CMD_CHAR_SIZE_INTO(buf: &mut [u8], h_magnify: u8, w_magnify: u8) -> Option<usize> {
+    let size = ((w_magnify.clamp(1, 8) - 1) << 4) | (h_magnify.clamp(1, 8) - 1);
+    write_cmd(buf, &[GS, b'!'], &[size])
+}
+
+// Header of `CMD_FEED_UNITS_INTO`, whose length sizes the buffer of `escpos::CMD_FEED_MM`
+pub(crate) const _CMD_FEED_UNITS: &[u8] = &[ESC, b'J'];
+def_cmd_into!(
+    /// `ESC J n`: feeds paper by `n` vertical motion units, without resetting the start of the
+    /// line
+    CMD_FEED_UNITS_INTO,
+    _CMD_FEED_UNITS,
+    units: u8
+);
+
+def_cmd_into!(
+    /// `ESC d n`: prints the buffered data and feeds paper by `n` lines
+    CMD_FEED_LINES_INTO,
+    &[ESC, b'd'],
+    lines: u8
+);
+
+def_cmd_into!(
+    /// `ESC K n`: prints the buffered data and feeds paper back by `n` vertical motion units.
+    /// Impact (slip) printers feed back further than thermal ones, which only go back a short
+    /// way (e.g. about 1.5mm on the TM-T88IV)
+    CMD_REVERSE_FEED_UNITS_INTO,
+    &[ESC, b'K'],
+    units: u8
+);
+
+/// Converts a length in millimeters into (the nearest number of) vertical motion units
+pub(crate) fn mm_to_motion_units(mm: f32, units_per_inch: u16) -> u32 {
+    // Rounded half up by hand, as `f32::round` is not available in `core`
+    (mm.max(0.0) / 25.4 * units_per_inch as f32 + 0.5) as u32
+}
+
+/// Feeds paper by `mm` millimeters (`ESC J`) in vertical motion units of `1 / units_per_inch`
+/// inch, split into as many commands as needed since each feeds at most 255 motion units
+#[allow(non_snake_case)]
+pub fn CMD_FEED_MM_INTO(buf: &mut [u8], mm: f32, units_per_inch: u16) -> Option<usize> {
+    let mut units = mm_to_motion_units(mm, units_per_inch);
+    let mut len = 0;
+    while units > 0 {
+        let feed = units.min(u8::MAX as u32);
+        len += CMD_FEED_UNITS_INTO(buf.get_mut(len..)?, feed as u8)?;
+        units -= feed;
+    }
+    Some(len)
+}
+
+def_cmd_into!(
+    /// `ESC B n t`: sounds the built-in buzzer `n` times, for `t * 50ms` each. Not part of
+    /// Epson's `ESC/POS` reference, but common among compatible printers with a buzzer
+    CMD_BEEP_INTO,
+    &[ESC, b'B'],
+    times: u8,
+    duration: u8
+);
+
+def_cmd_into!(
+    /// `ESC p m t1 t2`: sends a pulse on pin 2 (`m = 0`) or 5 (`m = 1`) of the drawer kick-out
+    /// connector, on for `t1 * 2ms` and then off for `t2 * 2ms`. Queued in the receive buffer
+    /// like any other command, so it waits for the job before it
+    CMD_PULSE_INTO,
+    &[ESC, b'p'],
+    pin: u8,
+    on_time: u8,
+    off_time: u8
+);
+
+def_cmd_into!(
+    /// `DLE DC4 fn=1 m t`: sends a pulse on pin 2 (`m = 0`) or 5 (`m = 1`) of the drawer
+    /// kick-out connector, on and then off for `t * 100ms` (1-8). Like `DLE EOT`, it is executed
+    /// as soon as it is received, even while the buffer is full or the printer is stalled
+    CMD_RT_GENERATE_PULSE_INTO,
+    &[DLE, DC4, 1],
+    pin: u8,
+    time: u8
+);
+
+/// `ESC $`: moves the print position to `dots` (in horizontal motion units, i.e. dots by
+/// default) from the start of the line. Only applies in left justification
+#[allow(non_snake_case)]
+pub fn CMD_ABS_POSITION_INTO(buf: &mut [u8], dots: u16) -> Option<usize> {
+    write_cmd(buf, &[ESC, b'$'], &dots.to_le_bytes())
+}
+
+/// `GS L`: sets the left margin in dots, which only takes effect at the start of a line. Lines
+/// wrapped by the printer start at the margin as well.
+#[allow(non_snake_case)]
+pub fn CMD_LEFT_MARGIN_INTO(buf: &mut [u8], dots: u16) -> Option<usize> {
+    write_cmd(buf, &[GS, b'L'], &dots.to_le_bytes())
+}
+
+// 2D symbol commands (`GS ( k`) for QR codes (`cn = 49`). The symbol is encoded and rasterized
+// by the printer itself, and always occupies its own band of lines
+pub(crate) const QR_MODULE_SIZE_RANGE: core::ops::RangeInclusive<u8> = 1..=16;
+
+/// `GS ( k` function 165: selects the QR code model, 49 for model 1 and 50 for model 2
+#[allow(non_snake_case)]
+pub fn CMD_QR_MODEL_INTO(buf: &mut [u8], model: u8) -> Option<usize> {
+    write_cmd(buf, &[GS, b'(', b'k', 4, 0, 49, 65], &[model, 0])
+}
+
+/// `GS ( k` function 167: sets the width of a QR code module (in dots, 1-16)
+#[allow(non_snake_case)]
+pub fn CMD_QR_MODULE_SIZE_INTO(buf: &mut [u8], dots: u8) -> Option<usize> {
+    let dots = dots.clamp(*QR_MODULE_SIZE_RANGE.start(), *QR_MODULE_SIZE_RANGE.end());
+    write_cmd(buf, &[GS, b'(', b'k', 3, 0, 49, 67], &[dots])
+}
+
+def_cmd_into!(
+    /// `GS ( k` function 169: sets the error correction level of QR codes, from 48 (L, 7%
+    /// recovery) to 51 (H, 30% recovery)
+    CMD_QR_ERROR_CORRECTION_INTO,
+    &[GS, b'(', b'k', 3, 0, 49, 69],
+    level: u8
+);
+
+/// `GS ( k` function 180: stores `data` in the QR code symbol storage area
+#[allow(non_snake_case)]
+pub fn CMD_QR_STORE_INTO(buf: &mut [u8], data: &[u8]) -> Option<usize> {
+    // The parameter length covers `cn`, `fn` and `m` as well
+    let [pL, pH] = u16::try_from(data.len() + 3).ok()?.to_le_bytes();
+    write_cmd(buf, &[GS, b'(', b'k', pL, pH, 49, 80, 48], data)
+}
+
+/// `GS k m n` (function B): prints `data` as a barcode of symbology `m`, e.g. 65 for UPC-A
+/// or 67 for EAN-13
+#[allow(non_snake_case)]
+pub fn CMD_BARCODE_INTO(buf: &mut [u8], symbology: u8, data: &[u8]) -> Option<usize> {
+    let len = u8::try_from(data.len()).ok()?;
+    write_cmd(buf, &[GS, b'k', symbology, len], data)
+}
+
+// Bytes of the define command that are covered by its parameter length, besides the data
+const GRAPHICS_DEFINE_PARAMS_LEN: usize = 11;
+
+// Largest raster (in bytes) whose length fits the parameter length of `GS ( L`
+pub(crate) const GRAPHICS_MAX_DATA_LEN: usize = u16::MAX as usize - GRAPHICS_DEFINE_PARAMS_LEN;
+
+/// `GS ( L` function 83: defines monochrome download graphics (`width` x `height` dots, with
+/// rows of `data` padded to whole bytes) under the `key` of two printable ASCII characters.
+/// They are kept in the printer's RAM until it is powered off
+#[allow(non_snake_case)]
+pub fn CMD_DEFINE_GRAPHICS_INTO(
+    buf: &mut [u8],
+    key: [u8; 2],
+    width: u16,
+    height: u16,
+    data: &[u8],
+) -> Option<usize> {
+    let [pL, pH] = u16::try_from(data.len() + GRAPHICS_DEFINE_PARAMS_LEN)
+        .ok()?
+        .to_le_bytes();
+    let [xL, xH] = width.to_le_bytes();
+    let [yL, yH] = height.to_le_bytes();
+    let [kc1, kc2] = key;
+    write_cmd(
+        buf,
+        &[
+            GS, b'(', b'L', pL, pH, 48, 83, 48, kc1, kc2, 1, xL, xH, yL, yH, 49,
+        ],
+        data,
+    )
+}
+
+// `ESC *` in 24-dot double-density mode: prints a bit image in line with the text, as columns
+// of 3 bytes with the top dot in the most significant bit of the first byte
+const BIT_IMAGE_24_MODE: u8 = 33;
+pub(crate) const BIT_IMAGE_24_HEIGHT_DOTS: usize = 24;
+
+/// `ESC *` in 24-dot double-density mode: prints `columns` (of 3 bytes each, with the top dot
+/// in the most significant bit of the first byte) as a bit image in line with the text
+#[allow(non_snake_case)]
+pub fn CMD_BIT_IMAGE_24_INTO(buf: &mut [u8], columns: &[u8]) -> Option<usize> {
+    if !columns.len().is_multiple_of(BIT_IMAGE_24_HEIGHT_DOTS / 8) {
+        return None;
+    }
+    let [nL, nH] = u16::try_from(columns.len() / (BIT_IMAGE_24_HEIGHT_DOTS / 8))
+        .ok()?
+        .to_le_bytes();
+    write_cmd(buf, &[ESC, b'*', BIT_IMAGE_24_MODE, nL, nH], columns)
+}
+
+/// `GS ( L` function 85: prints the download graphics defined under `key`, with each dot
+/// printed `x` times across and `y` times down (1 or 2)
+#[allow(non_snake_case)]
+pub fn CMD_PRINT_GRAPHICS_INTO(buf: &mut [u8], key: [u8; 2], x: u8, y: u8) -> Option<usize> {
+    let [kc1, kc2] = key;
+    write_cmd(buf, &[GS, b'(', b'L', 6, 0, 48, 85], &[kc1, kc2, x, y])
+}
+
+def_cmd_into!(
+    /// `ESC a n`: justifies the following lines left (0), centered (1) or right (2)
+    CMD_JUSTIFY_INTO,
+    &[ESC, b'a'],
+    n: u8
+);
+
+pub(crate) const PRINT_DENSITY_RANGE: core::ops::RangeInclusive<i8> = -6..=6;
+
+/// Sets the print density (`GS ( E` function 5, setting 5) in steps of 5%, from 70% (-6) to
+/// 130% (6) of the standard density (0). Only valid in user setting mode.
+///
+/// Supported by Epson TM series printers (e.g. the TM-T88IV). Other vendors use different
+/// commands (e.g. `DC2 # n`), which are not supported.
+#[allow(non_snake_case)]
+pub fn CMD_PRINT_DENSITY_INTO(buf: &mut [u8], level: i8) -> Option<usize> {
+    // Negative levels are encoded as `65536 + level`, i.e. as two's complement
+    let level = level.clamp(*PRINT_DENSITY_RANGE.start(), *PRINT_DENSITY_RANGE.end()) as i16;
+    write_cmd(buf, &[GS, b'(', b'E', 4, 0, 5, 5], &level.to_le_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builders_write_into_the_start_of_the_buffer() {
+        let mut buf = [0xFF; 8];
+        assert_eq!(CMD_BOLD_INTO(&mut buf, true), Some(3));
+        assert_eq!(buf, [ESC, b'E', 1, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(CMD_LEFT_MARGIN_INTO(&mut buf, 0x0168), Some(4));
+        assert_eq!(buf[..4], [GS, b'L', 0x68, 0x01]);
+    }
+
+    #[test]
+    fn builders_reject_a_short_buffer() {
+        let mut buf = [0; 2];
+        assert_eq!(CMD_BEEP_INTO(&mut buf, 3, 2), None);
+        assert_eq!(CMD_QR_STORE_INTO(&mut buf, b"data"), None);
+        assert_eq!(buf, [0; 2]);
+    }
+
+    #[test]
+    fn builders_reject_an_oversize_payload() {
+        let mut buf = vec![0; u16::MAX as usize + 8];
+        // The parameter length covers 3 bytes besides the data
+        let data = vec![b'0'; u16::MAX as usize - 2];
+        assert_eq!(
+            CMD_QR_STORE_INTO(&mut buf, &data[1..]),
+            Some(data.len() + 7)
+        );
+        assert_eq!(buf[3..5], [0xFF, 0xFF]);
+        assert_eq!(CMD_QR_STORE_INTO(&mut buf, &data), None);
+
+        assert_eq!(CMD_BARCODE_INTO(&mut buf, 73, &data[..255]), Some(259));
+        assert_eq!(buf[..4], [GS, b'k', 73, 255]);
+        assert_eq!(CMD_BARCODE_INTO(&mut buf, 73, &data[..256]), None);
+    }
+
+    #[test]
+    fn feed_mm_splits_into_commands_that_fit() {
+        // 25.4mm at 360 units per inch is 360 units, fed as 255 and 105
+        let mut buf = [0; 6];
+        assert_eq!(CMD_FEED_MM_INTO(&mut buf, 25.4, 360), Some(6));
+        assert_eq!(buf, [ESC, b'J', 255, ESC, b'J', 105]);
+        assert_eq!(CMD_FEED_MM_INTO(&mut buf[..5], 25.4, 360), None);
+        assert_eq!(CMD_FEED_MM_INTO(&mut [], 0.0, 360), Some(0));
+    }
+
+    #[test]
+    fn define_graphics_encodes_the_raster_size() {
+        let data = [0b1010_0000; 2];
+        let mut buf = [0; 18];
+        assert_eq!(
+            CMD_DEFINE_GRAPHICS_INTO(&mut buf, *b"LG", 3, 2, &data),
+            Some(18)
+        );
+        assert_eq!(
+            buf,
+            [
+                GS,
+                b'(',
+                b'L',
+                13,
+                0,
+                48,
+                83,
+                48,
+                b'L',
+                b'G',
+                1,
+                3,
+                0,
+                2,
+                0,
+                49,
+                0b1010_0000,
+                0b1010_0000,
+            ]
+        );
+    }
+}
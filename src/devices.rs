@@ -0,0 +1,134 @@
+//! Named printers, for setups with several printers (e.g. a receipt and a kitchen printer) that
+//! are selected by name rather than by their USB IDs or network address. They are listed in a
+//! TOML file, each with either its USB IDs or its host:
+//!
+//! ```toml
+//! [printers.receipt]
+//! vid = 0x04b8
+//! pid = 0x0202
+//!
+//! [printers.kitchen]
+//! host = "192.168.1.50:9100"
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    env,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::printer::{PrintyError, PrintyResult};
+
+/// Where a named printer is connected, by its USB IDs or by the host of a network printer
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NamedPrinter {
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    /// `HOST[:PORT]` of a network printer, as taken by `--host`
+    pub host: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Printers {
+    #[serde(default)]
+    printers: BTreeMap<String, NamedPrinter>,
+}
+
+impl Printers {
+    /// Default location of the printers file, `printy/printers.toml` in the user's config
+    /// directory (`$XDG_CONFIG_HOME`, or else `~/.config`)
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_dir.join("printy").join("printers.toml"))
+    }
+
+    pub fn load(path: &Path) -> PrintyResult<Self> {
+        let toml = std::fs::read_to_string(path).map_err(|e| PrintyError::Parse {
+            context: format!("Failed to read printers file {}", path.display()),
+            source: Some(Box::new(e)),
+        })?;
+        toml::from_str(&toml).map_err(|e| PrintyError::Parse {
+            context: format!("Invalid printers file {}", path.display()),
+            source: Some(Box::new(e)),
+        })
+    }
+
+    /// Looks up the printer named `name`, which must have either both USB IDs or a host
+    pub fn get(&self, name: &str) -> PrintyResult<NamedPrinter> {
+        let printer = self
+            .printers
+            .get(name)
+            .cloned()
+            .ok_or_else(|| PrintyError::Parse {
+                context: format!(
+                    "Unknown printer `{name}` (known printers: {})",
+                    match self.printers.keys().cloned().collect::<Vec<_>>() {
+                        names if names.is_empty() => "none".to_string(),
+                        names => names.join(", "),
+                    }
+                ),
+                source: None,
+            })?;
+        match (&printer.vid, &printer.pid, &printer.host) {
+            (Some(_), Some(_), None) | (None, None, Some(_)) => Ok(printer),
+            _ => Err(PrintyError::Parse {
+                context: format!("Printer `{name}` needs either `vid` and `pid`, or `host`"),
+                source: None,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printers(toml: &str) -> Printers {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn printers_are_named_by_usb_ids_or_host() {
+        let printers = printers(
+            r#"
+            [printers.receipt]
+            vid = 0x04b8
+            pid = 0x0202
+
+            [printers.kitchen]
+            host = "192.168.1.50:9100"
+            "#,
+        );
+        let receipt = printers.get("receipt").unwrap();
+        assert_eq!((receipt.vid, receipt.pid), (Some(0x04b8), Some(0x0202)));
+        assert_eq!(receipt.host, None);
+        let kitchen = printers.get("kitchen").unwrap();
+        assert_eq!((kitchen.vid, kitchen.pid), (None, None));
+        assert_eq!(kitchen.host.as_deref(), Some("192.168.1.50:9100"));
+        assert!(printers.get("bar").is_err());
+    }
+
+    #[test]
+    fn printers_need_usb_ids_or_host() {
+        let printers = printers(
+            r#"
+            [printers.vid_only]
+            vid = 0x04b8
+
+            [printers.both]
+            vid = 0x04b8
+            pid = 0x0202
+            host = "kitchen"
+            "#,
+        );
+        assert!(printers.get("vid_only").is_err());
+        assert!(printers.get("both").is_err());
+    }
+}
@@ -1,65 +1,165 @@
+use std::collections::HashMap;
 use std::fmt::Display;
 
 use derive_builder::Builder;
+use serde::Serialize;
 
+use crate::commands::{self, _CMD_FEED_UNITS, DC4, DLE, EOT, ESC, GS};
+pub(crate) use crate::commands::{
+    BIT_IMAGE_24_HEIGHT_DOTS, GRAPHICS_MAX_DATA_LEN, mm_to_motion_units,
+};
+use crate::raster::Raster;
+
+// Defines a command returning a `Vec`, written by its builder in `commands` from the values
+// that the (typed) parameters encode
 macro_rules! def_cmd {
-    ($fn_name:ident, $header:expr, $( $param_name:ident : $param_type:ty ),+) => {
+    ($fn_name:ident, $into_fn_name:ident, $( $param_name:ident : $param_type:ty ),+) => {
         #[allow(non_snake_case)]
         pub(crate) fn $fn_name($( $param_name: $param_type ),+) -> Vec<u8> {
-            [$header, &[$($param_name as u8),+]].concat()
+            cmd_vec(|buf| commands::$into_fn_name(buf, $( $param_name as _ ),+))
         }
     };
 }
 
-const ESC: u8 = 0x1B;
-const DLE: u8 = 0x10;
-const EOT: u8 = 0x04;
-const GS: u8 = 0x1D;
+// Upper bound for the length of a command without a variable-length payload
+const MAX_CMD_LEN: usize = 16;
+
+fn cmd_vec(write_into: impl FnOnce(&mut [u8]) -> Option<usize>) -> Vec<u8> {
+    let mut buf = [0u8; MAX_CMD_LEN];
+    let len = write_into(&mut buf).expect("command should not exceed MAX_CMD_LEN");
+    buf[..len].to_vec()
+}
+
+const ENQ: u8 = 0x05;
+const FS: u8 = 0x1C;
 
 pub(crate) const CMD_INIT: &[u8] = &[ESC, b'@'];
 
-const _CMD_RT_STATUS: &[u8] = &[DLE, EOT];
+#[derive(Clone, Copy)]
 pub(crate) enum RtStatusReq {
     PrinterStatus = 1,
     OfflineCause = 2,
     ErrorCause = 3,
     PaperStatus = 4,
 }
-def_cmd!(CMD_RT_STATUS, _CMD_RT_STATUS, req: RtStatusReq);
+def_cmd!(CMD_RT_STATUS, CMD_RT_STATUS_INTO, req: RtStatusReq);
 
-#[derive(Debug, Builder, Clone)]
-pub(crate) struct PrinterError {
+/// Error reported by `DLE EOT` while the printer is offline.
+///
+/// `DLE EOT n=3` only reports the class of an error, not its cause: a print head temperature
+/// error (e.g. thermal protection in a hot environment) is reported as an auto-recoverable
+/// error, and a supply voltage error as an unrecoverable (fatal) one. They cannot be told apart
+/// from the other errors of their class.
+#[derive(Debug, Builder, Clone, Serialize)]
+pub struct PrinterError {
+    // `DLE EOT n=3`, bit 3
     is_cutter_err: bool,
+    // `DLE EOT n=3`, bit 5: e.g. a supply voltage or memory error, cleared by power cycling
     is_fatal_err: bool,
+    // `DLE EOT n=3`, bit 6: e.g. a print head temperature error, cleared once the head cools
+    // down
     is_recoverable_err: bool,
+    // `DLE EOT n=1`, bit 5: the error was cleared, but the printer is not back online yet
+    is_waiting_recovery: bool,
 }
 
-#[derive(Debug, Builder, Clone)]
-pub(crate) struct OfflineCause {
+/// Why the printer is offline, as reported by `DLE EOT`
+#[derive(Debug, Builder, Clone, Serialize)]
+pub struct OfflineCause {
     is_cover_open: bool,
     is_paper_empty: bool,
     error: Option<PrinterError>,
 }
 
-#[derive(Debug, Clone)]
-pub(crate) enum PaperStatus {
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaperStatus {
     Adequate,
     NearEnd,
     NotPresent,
 }
 
-#[derive(Debug, Builder)]
-pub(crate) struct PrinterStatus {
+#[derive(Debug, Builder, Serialize)]
+pub struct PrinterStatus {
     is_online: bool,
     offline_cause: Option<OfflineCause>,
     paper_status: PaperStatus,
 }
 
+// Size of the buffer a `DLE EOT` or `GS r` reply is read into, which leaves room for bytes some
+// transports (or a previous, unread reply) send along with it
+pub(crate) const RT_STATUS_BUF_LEN: usize = 16;
+
+// `DLE EOT` is processed as soon as it is received, so its reply does not take long
+pub(crate) const RT_STATUS_REPLY_DELAY_MS: u64 = 100;
+
+// Requests that make up a `PrinterStatus`, in the order `PrinterStatus::from_bytes` expects
+pub(crate) const RT_STATUS_REQS: [RtStatusReq; 4] = [
+    RtStatusReq::PrinterStatus,
+    RtStatusReq::OfflineCause,
+    RtStatusReq::ErrorCause,
+    RtStatusReq::PaperStatus,
+];
+
+// All `DLE EOT` replies have bits 0 and 7 unset, and bits 1 and 4 set. The fixed bits are the
+// same for each of `n=1` (printer), `n=2` (offline cause), `n=3` (error cause) and `n=4` (roll
+// paper sensor), which only differ in the meaning of bits 2-3 and 5-6, so a single mask
+// validates the reply to any of them.
+fn is_rt_status_byte(byte: u8) -> bool {
+    (byte & 0b10010011) == 0b00010010
+}
+
+/// Picks the reply to a single `DLE EOT` request out of the bytes read after it, skipping any
+/// bytes that cannot be a reply and whole `ASB` messages, whose other bytes may look like
+/// replies. The last reply wins, as earlier ones may be stale.
+pub(crate) fn rt_status_byte(reply: &[u8]) -> Option<u8> {
+    let mut last_reply = None;
+    let mut idx = 0;
+    while let Some(&byte) = reply.get(idx) {
+        // Replies have bit 1 set, so they are never taken for an `ASB` header
+        if is_asb_header(byte) && idx + ASB_LEN <= reply.len() {
+            idx += ASB_LEN;
+            continue;
+        }
+        if is_rt_status_byte(byte) {
+            last_reply = Some(byte);
+        }
+        idx += 1;
+    }
+    last_reply
+}
+
+// Length of an `ASB` (Automatic Status Back) message
+const ASB_LEN: usize = 4;
+
+// The first byte of an `ASB` message has bits 0, 1 and 7 unset, and bit 4 set
+fn is_asb_header(byte: u8) -> bool {
+    (byte & 0b10010011) == 0b00010000
+}
+
+/// Picks the reply to a `GS r` request out of the bytes read after it, which the printer may
+/// send in the same transfer as `ASB` messages (e.g. 4 `ASB` bytes followed by the reply). The
+/// `ASB` messages are skipped, and only the first reply is taken.
+pub(crate) fn transmit_status_byte(reply: &[u8]) -> Option<u8> {
+    let mut idx = 0;
+    while let Some(&byte) = reply.get(idx) {
+        // `GS r` replies have bit 4 unset, so they are never taken for an `ASB` header
+        if is_asb_header(byte) && idx + ASB_LEN <= reply.len() {
+            idx += ASB_LEN;
+            continue;
+        }
+        return Some(byte);
+    }
+    None
+}
+
 impl PrinterStatus {
-    pub(crate) fn from_bytes(bytes: &[u8; 4]) -> Option<Self> {
+    /// Parses the replies to `DLE EOT n=1-4` (in this order), or returns `None` if any of them
+    /// is not a `DLE EOT` reply
+    pub fn from_bytes(bytes: &[u8; 4]) -> Option<Self> {
         // All bit masks used below are based on the ESC/POS `DLE EOT` status response format.
         // Reference: https://download4.epson.biz/sec_pubs/pos/reference_en/escpos/dle_eot.html
-        if !bytes.iter().all(|&b| (b & 0b10010011) == 0b00010010) {
+        if !bytes.iter().all(|&b| is_rt_status_byte(b)) {
             // If the status bytes do not match the expected format, stop parsing and treat
             // it as an indeterminate status
             return None;
@@ -73,6 +173,7 @@ impl PrinterStatus {
                 .is_cutter_err((err_cause_b & 0b1000) != 0)
                 .is_fatal_err((err_cause_b & 0b100000) != 0)
                 .is_recoverable_err((err_cause_b & 0b1000000) != 0)
+                .is_waiting_recovery((dev_status_b & 0b100000) != 0)
                 .build()
                 .ok()
         } else {
@@ -96,6 +197,8 @@ impl PrinterStatus {
             // 1. End sensor takes priority - if it detects no paper, status is `NotPresent` regardless
             //    of near-end sensor
             // 2. If end sensor detects paper is present, then check near-end sensor:
+            // `DLE EOT n=4` reports the roll end sensor in bits 5-6 and the near-end sensor in
+            // bits 2-3, each pair being set together
             .paper_status(match paper_status_b {
                 paper_status_b if (paper_status_b & 0b1100000) != 0 => PaperStatus::NotPresent,
                 paper_status_b if (paper_status_b & 0b1100) != 0 => PaperStatus::NearEnd,
@@ -107,6 +210,85 @@ impl PrinterStatus {
     }
 }
 
+impl PrinterError {
+    pub fn is_cutter_err(&self) -> bool {
+        self.is_cutter_err
+    }
+
+    pub fn is_fatal_err(&self) -> bool {
+        self.is_fatal_err
+    }
+
+    pub fn is_recoverable_err(&self) -> bool {
+        self.is_recoverable_err
+    }
+
+    pub fn is_waiting_recovery(&self) -> bool {
+        self.is_waiting_recovery
+    }
+
+    fn issues(&self) -> Vec<&'static str> {
+        [
+            (self.is_fatal_err, "fatal-error"),
+            (self.is_recoverable_err, "auto-recovery"),
+            (self.is_cutter_err, "cutter-error"),
+            (self.is_waiting_recovery, "waiting-recovery"),
+        ]
+        .into_iter()
+        .filter_map(|(is_set, issue)| is_set.then_some(issue))
+        .collect()
+    }
+}
+
+impl Display for PrinterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.issues().as_slice() {
+            [] => write!(f, "unknown-error"),
+            issues => write!(f, "{}", issues.join(", ")),
+        }
+    }
+}
+
+impl OfflineCause {
+    pub fn is_cover_open(&self) -> bool {
+        self.is_cover_open
+    }
+
+    pub fn is_paper_empty(&self) -> bool {
+        self.is_paper_empty
+    }
+
+    pub fn error(&self) -> Option<&PrinterError> {
+        self.error.as_ref()
+    }
+}
+
+impl Display for OfflineCause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut issues = self.error.as_ref().map(|e| e.issues()).unwrap_or_default();
+        if self.is_cover_open {
+            issues.push("cover-open");
+        }
+        if self.is_paper_empty {
+            issues.push("no-paper");
+        }
+        match issues.as_slice() {
+            [] => write!(f, "unknown"),
+            issues => write!(f, "{}", issues.join(", ")),
+        }
+    }
+}
+
+impl Display for PaperStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaperStatus::Adequate => write!(f, "OK"),
+            PaperStatus::NearEnd => write!(f, "LOW"),
+            PaperStatus::NotPresent => write!(f, "EMPTY"),
+        }
+    }
+}
+
 impl Display for PrinterStatus {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         const GREEN: &str = "\x1b[32;1m";
@@ -121,43 +303,245 @@ impl Display for PrinterStatus {
             format!("{RED}OFFLINE{RESET}")
         };
 
-        let paper_text = match self.paper_status {
-            PaperStatus::Adequate => format!("{GREEN}OK{RESET}"),
-            PaperStatus::NotPresent => format!("{RED}EMPTY{RESET}"),
-            PaperStatus::NearEnd => format!("{YELLOW}LOW{RESET}"),
+        let paper_color = match self.paper_status {
+            PaperStatus::Adequate => GREEN,
+            PaperStatus::NotPresent => RED,
+            PaperStatus::NearEnd => YELLOW,
         };
+        let paper_text = format!("{paper_color}{}{RESET}", self.paper_status);
 
         write!(f, "Status: {status_text} - Paper: {paper_text}")?;
 
-        if !self.is_online {
-            if let Some(cause) = &self.offline_cause {
-                let mut issues = Vec::new();
-
-                if let Some(error) = &cause.error {
-                    if error.is_fatal_err {
-                        issues.push(format!("{RED}fatal-error{RESET}"));
-                    }
-                    if error.is_recoverable_err {
-                        issues.push(format!("{YELLOW}auto-recovery{RESET}"));
-                    }
-                    if error.is_cutter_err {
-                        issues.push(format!("{MAGENTA}cutter-error{RESET}"));
-                    }
-                }
+        // Issues are colored by the most severe of them
+        if !self.is_online
+            && let Some(cause) = &self.offline_cause
+            && (cause.error.is_some() || cause.is_cover_open || cause.is_paper_empty)
+        {
+            let issues_color = match &cause.error {
+                Some(error) if error.is_fatal_err => RED,
+                Some(_) => YELLOW,
+                None => MAGENTA,
+            };
+            write!(f, " - Issues: {issues_color}{cause}{RESET}")?;
+        }
 
-                if cause.is_cover_open {
-                    issues.push(format!("{MAGENTA}cover-open{RESET}"));
-                }
-                if cause.is_paper_empty {
-                    issues.push(format!("{MAGENTA}no-paper{RESET}"));
-                }
+        Ok(())
+    }
+}
 
-                if !issues.is_empty() {
-                    write!(f, " - Issues: {}", issues.join(", "))?;
-                }
-            }
+pub(crate) enum TransmitStatusReq {
+    PaperSensor = 1,
+    Drawer = 2,
+}
+def_cmd!(CMD_TRANSMIT_STATUS, CMD_TRANSMIT_STATUS_INTO, req: TransmitStatusReq);
+
+// All bit masks used below are based on the ESC/POS `GS r` status response format.
+// Reference: https://download4.epson.biz/sec_pubs/pos/reference_en/escpos/gs_lr.html
+impl PaperStatus {
+    pub(crate) fn from_transmit_byte(byte: u8) -> Option<Self> {
+        if (byte & 0b10010000) != 0 {
+            return None;
+        }
+        // Same sensor priority as `PrinterStatus::from_bytes`. `GS r n=1` reports the roll end
+        // sensor in bits 2-3 and the near-end sensor in bits 0-1.
+        match byte {
+            byte if (byte & 0b1100) != 0 => Some(PaperStatus::NotPresent),
+            byte if (byte & 0b11) != 0 => Some(PaperStatus::NearEnd),
+            _ => Some(PaperStatus::Adequate),
+        }
+    }
+}
+
+/// Parses the drawer kick-out connector status, returning whether pin 3 is HIGH
+pub(crate) fn drawer_pin_high_from_transmit_byte(byte: u8) -> Option<bool> {
+    if (byte & 0b10010000) != 0 {
+        return None;
+    }
+    Some((byte & 0b1) != 0)
+}
+
+impl PrinterStatus {
+    /// Whether the printer is online with paper loaded, i.e. able to print
+    pub fn is_ready(&self) -> bool {
+        self.is_online && !matches!(self.paper_status, PaperStatus::NotPresent)
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.is_online
+    }
+
+    /// Why the printer is offline, if it is and the cause is known (only `DLE EOT` reports it)
+    pub fn offline_cause(&self) -> Option<&OfflineCause> {
+        self.offline_cause.as_ref()
+    }
+
+    pub fn paper_status(&self) -> &PaperStatus {
+        &self.paper_status
+    }
+
+    /// Treats a near-end paper status as adequate, for printers without a near-end sensor
+    /// (which is optional on some models, e.g. the TM-T88IV) that report it spuriously
+    pub fn ignoring_near_end(mut self) -> Self {
+        if self.paper_status == PaperStatus::NearEnd {
+            self.paper_status = PaperStatus::Adequate;
         }
+        self
+    }
 
+    /// Builds a status from the paper sensor reply of `GS r`.
+    ///
+    /// `GS r` is only processed once the printer reaches it in the receive buffer, which it
+    /// does not while offline. A reply therefore implies that the printer is online.
+    pub(crate) fn from_transmit_byte(paper_sensor_b: u8) -> Option<Self> {
+        PrinterStatusBuilder::default()
+            .is_online(true)
+            .paper_status(PaperStatus::from_transmit_byte(paper_sensor_b)?)
+            .offline_cause(None)
+            .build()
+            .ok()
+    }
+}
+
+pub(crate) enum IdentityReq {
+    Model = 1,
+    // Requests from 65 on are answered with a string (see `parse_identity_string`)
+    Firmware = 65,
+    Maker = 66,
+    ModelName = 67,
+}
+def_cmd!(CMD_IDENTITY, CMD_IDENTITY_INTO, req: IdentityReq);
+
+// Upper bound for the length of a `GS I` string reply, which is at most 80 bytes
+pub(crate) const IDENTITY_STRING_MAX_LEN: usize = 80;
+
+/// Parses a string reply to `GS I` (`0x5F`, followed by the string and a `NUL`), keeping the
+/// printable ASCII characters. A reply cut short (without a terminator) is parsed as far as it
+/// goes.
+pub(crate) fn parse_identity_string(reply: &[u8]) -> Option<String> {
+    let start = reply.iter().position(|&b| b == 0x5F)? + 1;
+    let string = reply[start..]
+        .iter()
+        .take_while(|&&b| b != 0)
+        .filter(|b| b.is_ascii_graphic() || **b == b' ')
+        .map(|&b| b as char)
+        .collect::<String>();
+    Some(string.trim().to_string()).filter(|string| !string.is_empty())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Feature {
+    Cutter,
+    Qr,
+    Buzzer,
+    NvGraphics,
+    /// Italics (`ESC 4`), which Star models print as reverse video instead
+    Italic,
+    Reverse,
+    /// Feeding paper backwards (`ESC K`), e.g. to print over a line again. Thermal models only
+    /// feed back a short way (about 1.5mm on the TM-T88IV), which is enough to overprint a line.
+    ReverseFeed,
+}
+
+impl Display for Feature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Feature::Cutter => "Autocutter",
+            Feature::Qr => "QR codes (GS ( k)",
+            Feature::Buzzer => "Buzzer",
+            Feature::NvGraphics => "NV graphics (GS ( L)",
+            Feature::Italic => "Italics (ESC 4)",
+            Feature::Reverse => "Reverse printing (GS B)",
+            Feature::ReverseFeed => "Reverse feed (ESC K)",
+        })
+    }
+}
+
+#[derive(Debug)]
+struct ModelCaps {
+    model_id: u8,
+    name: &'static str,
+    features: &'static [Feature],
+    // Width of the print area with the default margins (`GS L`, `GS W`), in dots
+    print_width_dots: u16,
+    // Maximum print speed, in millimeters of paper per second
+    print_speed_mm_s: f32,
+}
+
+// Models are keyed by the model ID reported by `GS I n=1`. Only the TM-T88IV, which this tool is
+// developed against, is mapped for now.
+const MODEL_CAPS: &[ModelCaps] = &[ModelCaps {
+    model_id: 0x20,
+    name: "TM-T88IV",
+    features: &[
+        Feature::Cutter,
+        Feature::NvGraphics,
+        Feature::Reverse,
+        Feature::ReverseFeed,
+    ],
+    // On 80mm paper
+    print_width_dots: 512,
+    print_speed_mm_s: 150.0,
+}];
+
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    model_id: u8,
+    model: Option<&'static ModelCaps>,
+}
+
+impl Capabilities {
+    pub(crate) fn from_model_id(model_id: u8) -> Self {
+        Self {
+            model_id,
+            model: MODEL_CAPS.iter().find(|caps| caps.model_id == model_id),
+        }
+    }
+
+    /// Returns whether the model supports `feature`, or `None` if the model is unknown
+    pub fn supports(&self, feature: Feature) -> Option<bool> {
+        self.model.map(|caps| caps.features.contains(&feature))
+    }
+
+    pub fn model_name(&self) -> &'static str {
+        self.model.map_or("Unknown", |caps| caps.name)
+    }
+
+    /// Returns the width of the print area in dots, or `None` if the model is unknown
+    pub fn print_width_dots(&self) -> Option<u16> {
+        self.model.map(|caps| caps.print_width_dots)
+    }
+
+    /// Returns the maximum print speed in millimeters per second, or `None` if the model is
+    /// unknown
+    pub fn print_speed_mm_s(&self) -> Option<f32> {
+        self.model.map(|caps| caps.print_speed_mm_s)
+    }
+}
+
+impl Display for Capabilities {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Model: {} (ID {:#04x})",
+            self.model_name(),
+            self.model_id
+        )?;
+        for feature in [
+            Feature::Cutter,
+            Feature::Qr,
+            Feature::Buzzer,
+            Feature::NvGraphics,
+            Feature::Italic,
+            Feature::Reverse,
+            Feature::ReverseFeed,
+        ] {
+            let support = match self.supports(feature) {
+                Some(true) => "yes",
+                Some(false) => "no",
+                None => "unknown",
+            };
+            writeln!(f, "  {feature}: {support}")?;
+        }
         Ok(())
     }
 }
@@ -168,27 +552,1162 @@ pub(crate) const CMD_DISABLE_ASB: &[u8] = &[GS, b'a', 0];
 // n is set to 0, which means the printer will cut right after the last printed line
 pub(crate) const CMD_CUT: &[u8] = &[GS, b'V', 66, 0];
 
-pub(crate) const _CMD_BOLD: &[u8] = &[ESC, b'E'];
-def_cmd!(CMD_BOLD, _CMD_BOLD, enable: bool);
+// The commands below are written by their builders in `commands`, which document them
+def_cmd!(CMD_CUT_FEED, CMD_CUT_FEED_INTO, units: u8);
+def_cmd!(CMD_CODEPAGE, CMD_CODEPAGE_INTO, page: u8);
+def_cmd!(CMD_ROTATE_90, CMD_ROTATE_90_INTO, enable: bool);
+def_cmd!(CMD_FONT_B, CMD_FONT_B_INTO, enable: bool);
+def_cmd!(CMD_BOLD, CMD_BOLD_INTO, enable: bool);
+def_cmd!(CMD_UNDERLINE, CMD_UNDERLINE_INTO, enable: bool);
+def_cmd!(CMD_REVERSE, CMD_REVERSE_INTO, enable: bool);
+
+// Italics are turned on by `ESC 4` and off by `ESC 5`, which comes from Epson's `ESC/P` for dot
+// matrix printers and is kept by some `ESC/POS` clones, but is not part of `ESC/POS` itself (TM
+// series printers lack italics). Star Line Mode uses the same commands for highlight (reverse)
+// printing, so Star models must not be mapped as supporting italics.
+#[allow(non_snake_case)]
+pub(crate) fn CMD_ITALIC(enable: bool) -> &'static [u8] {
+    if enable { &[ESC, b'4'] } else { &[ESC, b'5'] }
+}
+
+def_cmd!(CMD_CHAR_SIZE, CMD_CHAR_SIZE_INTO, h_magnify: u8, w_magnify: u8);
+
+// Vertical motion unit (`GS P`) of the TM-T88IV, which defaults to 1/360 inch
+pub(crate) const VERTICAL_MOTION_UNITS_PER_INCH: u16 = 360;
+
+/// Longest feed past the cutting position that `GS V 66 n` can do, in millimeters
+pub const MAX_CUT_FEED_MM: f32 = u8::MAX as f32 / VERTICAL_MOTION_UNITS_PER_INCH as f32 * 25.4;
+
+/// Converts a feed past the cutting position into the `n` of `GS V 66 n`, clamped to
+/// [`MAX_CUT_FEED_MM`]
+pub(crate) fn cut_feed_units(mm: f32, units_per_inch: u16) -> u8 {
+    mm_to_motion_units(mm, units_per_inch).min(u8::MAX as u32) as u8
+}
+
+def_cmd!(CMD_FEED_LINES, CMD_FEED_LINES_INTO, lines: u8);
+def_cmd!(CMD_REVERSE_FEED_UNITS, CMD_REVERSE_FEED_UNITS_INTO, units: u8);
+
+#[allow(non_snake_case)]
+pub(crate) fn CMD_FEED_MM(mm: f32, units_per_inch: u16) -> Vec<u8> {
+    let feeds = mm_to_motion_units(mm, units_per_inch).div_ceil(u8::MAX as u32) as usize;
+    let mut cmds = vec![0u8; feeds * (_CMD_FEED_UNITS.len() + 1)];
+    let len = commands::CMD_FEED_MM_INTO(&mut cmds, mm, units_per_inch).unwrap_or_default();
+    cmds.truncate(len);
+    cmds
+}
+
+def_cmd!(CMD_BEEP, CMD_BEEP_INTO, times: u8, duration: u8);
+
+/// Pin of the drawer kick-out connector that a pulse is sent on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DrawerPin {
+    Pin2 = 0,
+    Pin5 = 1,
+}
+
+def_cmd!(CMD_PULSE, CMD_PULSE_INTO, pin: DrawerPin, on_time: u8, off_time: u8);
+def_cmd!(CMD_RT_GENERATE_PULSE, CMD_RT_GENERATE_PULSE_INTO, pin: DrawerPin, time: u8);
+
+// `DLE DC4 fn=8`: discards all data in the receive and print buffers, executed as soon as it is
+// received. The printer acknowledges it with `RT_CLEAR_BUFFER_REPLY` once done
+pub(crate) const CMD_RT_CLEAR_BUFFER: &[u8] = &[DLE, DC4, 8, 1, 3, 20, 1, 6, 2, 8];
+pub(crate) const RT_CLEAR_BUFFER_REPLY: &[u8] = &[0x37, 0x25, 0x00];
+
+def_cmd!(CMD_ABS_POSITION, CMD_ABS_POSITION_INTO, dots: u16);
+def_cmd!(CMD_LEFT_MARGIN, CMD_LEFT_MARGIN_INTO, dots: u16);
+
+/// QR code model. Model 2 is the common one, while model 1 (which holds less data) is the
+/// only one that some older scanners read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum QrModel {
+    #[value(name = "1")]
+    Model1 = 49,
+    #[value(name = "2")]
+    Model2 = 50,
+}
+
+impl QrModel {
+    /// Maximum number of bytes a code of this model holds at the error correction `level`,
+    /// i.e. at its largest version (14 for model 1, 40 for model 2).
+    ///
+    /// Only the level L capacity of model 1 is published by Epson, the others are scaled from
+    /// it by the ratios between the levels of model 2, which makes them approximate.
+    pub fn max_data_len(&self, level: QrErrorCorrection) -> usize {
+        match (self, level) {
+            (QrModel::Model1, QrErrorCorrection::L) => 486,
+            (QrModel::Model1, QrErrorCorrection::M) => 383,
+            (QrModel::Model1, QrErrorCorrection::Q) => 273,
+            (QrModel::Model1, QrErrorCorrection::H) => 209,
+            (QrModel::Model2, QrErrorCorrection::L) => 2953,
+            (QrModel::Model2, QrErrorCorrection::M) => 2331,
+            (QrModel::Model2, QrErrorCorrection::Q) => 1663,
+            (QrModel::Model2, QrErrorCorrection::H) => 1273,
+        }
+    }
+}
+
+def_cmd!(CMD_QR_MODEL, CMD_QR_MODEL_INTO, model: QrModel);
+def_cmd!(CMD_QR_MODULE_SIZE, CMD_QR_MODULE_SIZE_INTO, dots: u8);
+def_cmd!(CMD_QR_ERROR_CORRECTION, CMD_QR_ERROR_CORRECTION_INTO, level: QrErrorCorrection);
+
+/// Error correction level of a QR code, i.e. the share of the code that can be damaged (or
+/// smudged) while remaining readable. Higher levels make for larger codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QrErrorCorrection {
+    /// 7% recovery
+    L = 48,
+    /// 15% recovery
+    M = 49,
+    /// 25% recovery
+    Q = 50,
+    /// 30% recovery
+    H = 51,
+}
+
+impl Display for QrErrorCorrection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[allow(non_snake_case)]
+pub(crate) fn CMD_QR_STORE(data: &[u8]) -> Vec<u8> {
+    let mut cmd = vec![0u8; data.len() + 8];
+    let len = commands::CMD_QR_STORE_INTO(&mut cmd, data).unwrap_or_default();
+    cmd.truncate(len);
+    cmd
+}
+
+// Function 181: prints the symbol in the symbol storage area
+pub(crate) const CMD_QR_PRINT: &[u8] = &[GS, b'(', b'k', 3, 0, 49, 81, 48];
+
+// Module size for captioned QR codes, small enough to keep e.g. a Wi-Fi QR code (version 3-5)
+// under a third of the print width
+pub(crate) const QR_CAPTION_MODULE_SIZE: u8 = 4;
+
+/// Numeric barcode symbology (`GS k`, function B), which the printer encodes and prints
+/// (along with the digits below it) on its own band of lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Symbology {
+    #[value(name = "upc-a")]
+    UpcA = 65,
+    Ean13 = 67,
+    Ean8 = 68,
+}
+
+impl Symbology {
+    /// Number of digits of a barcode of this symbology, including its check digit
+    pub fn digits(&self) -> usize {
+        match self {
+            Symbology::UpcA => 12,
+            Symbology::Ean13 => 13,
+            Symbology::Ean8 => 8,
+        }
+    }
+}
+
+/// Computes the EAN/UPC check digit of `digits` (ASCII digits, without the check digit): the
+/// digits are weighted 3 and 1 alternately from the rightmost one, and the check digit rounds
+/// their sum up to a multiple of 10
+pub fn check_digit(digits: &[u8]) -> u8 {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(idx, digit)| u32::from(digit - b'0') * if idx % 2 == 0 { 3 } else { 1 })
+        .sum();
+    b'0' + ((10 - sum % 10) % 10) as u8
+}
+
+// Prints the digits of barcodes below them (`GS H n=2`)
+pub(crate) const CMD_BARCODE_HRI_BELOW: &[u8] = &[GS, b'H', 2];
+
+#[allow(non_snake_case)]
+pub(crate) fn CMD_BARCODE(symbology: Symbology, data: &[u8]) -> Vec<u8> {
+    let mut cmd = vec![0u8; data.len() + 4];
+    let len = commands::CMD_BARCODE_INTO(&mut cmd, symbology as u8, data).unwrap_or_default();
+    cmd.truncate(len);
+    cmd
+}
+
+// Download graphics (`GS ( L` functions 83 and 85, raster format) are kept in the printer's RAM
+// until it is powered off, and recalled by a key of two printable ASCII characters
+pub(crate) const GRAPHICS_KEY_RANGE: std::ops::RangeInclusive<u8> = 32..=126;
+
+#[allow(non_snake_case)]
+pub(crate) fn CMD_DEFINE_GRAPHICS(key: [u8; 2], raster: &Raster) -> Vec<u8> {
+    let mut cmd = vec![0u8; raster.data().len() + 16];
+    let len = commands::CMD_DEFINE_GRAPHICS_INTO(
+        &mut cmd,
+        key,
+        raster.width(),
+        raster.height(),
+        raster.data(),
+    )
+    .unwrap_or_default();
+    cmd.truncate(len);
+    cmd
+}
+
+#[allow(non_snake_case)]
+pub(crate) fn CMD_BIT_IMAGE_24(columns: &[u8]) -> Vec<u8> {
+    let mut cmd = vec![0u8; columns.len() + 5];
+    let len = commands::CMD_BIT_IMAGE_24_INTO(&mut cmd, columns).unwrap_or_default();
+    cmd.truncate(len);
+    cmd
+}
 
-pub(crate) const _CMD_UNDERLINE: &[u8] = &[ESC, b'-'];
-def_cmd!(CMD_UNDERLINE, _CMD_UNDERLINE, enable: bool);
+/// Size that graphics are printed at, by printing each dot once or twice across and down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ImageDensity {
+    Normal,
+    DoubleW,
+    DoubleH,
+    Quad,
+}
+
+impl ImageDensity {
+    /// Horizontal and vertical magnification (1 or 2) of each dot
+    pub fn scale(&self) -> (u8, u8) {
+        match self {
+            ImageDensity::Normal => (1, 1),
+            ImageDensity::DoubleW => (2, 1),
+            ImageDensity::DoubleH => (1, 2),
+            ImageDensity::Quad => (2, 2),
+        }
+    }
+}
 
 #[allow(non_snake_case)]
-pub(crate) fn CMD_CHAR_SIZE(h_magnify: u8, w_magnify: u8) -> Vec<u8> {
-    vec![
-        GS,
-        b'!',
-        (w_magnify.clamp(0, 8) << 4) | h_magnify.clamp(0, 8),
-    ]
+pub(crate) fn CMD_PRINT_GRAPHICS(key: [u8; 2], density: ImageDensity) -> Vec<u8> {
+    let (x, y) = density.scale();
+    cmd_vec(|buf| commands::CMD_PRINT_GRAPHICS_INTO(buf, key, x, y))
 }
 
-pub(crate) const _CMD_JUSTIFY: &[u8] = &[ESC, b'a'];
 pub(crate) enum JustifyReq {
     Left = 0,
     Center = 1,
     Right = 2,
 }
-def_cmd!(CMD_JUSTIFY, _CMD_JUSTIFY, req: JustifyReq);
+def_cmd!(CMD_JUSTIFY, CMD_JUSTIFY_INTO, req: JustifyReq);
+
+// User setting mode (`GS ( E`) is required to change the customized setting values, which are
+// stored in NV memory. Exiting the mode performs a software reset of the printer.
+pub(crate) const CMD_USER_SETTING_ENTER: &[u8] = &[GS, b'(', b'E', 3, 0, 1, b'I', b'N'];
+pub(crate) const CMD_USER_SETTING_EXIT: &[u8] = &[GS, b'(', b'E', 4, 0, 2, b'O', b'U', b'T'];
+
+def_cmd!(CMD_PRINT_DENSITY, CMD_PRINT_DENSITY_INTO, level: i8);
 
 pub(crate) const CMD_PROC_DELAY_MS: u64 = 500;
+
+// Characters per line for Font A (12 dots wide) on 80mm paper, with the TM-T88IV's 512 dots
+// (72mm) print width. On 58mm paper (360 dots, 50.8mm) it fits 30.
+pub const DEFAULT_COLUMNS: usize = 42;
+
+// Characters per line for Font B (9 dots wide) on 80mm paper. On 58mm paper it fits 40.
+pub const DEFAULT_FONT_B_COLUMNS: usize = 56;
+
+// Width of a Font A character (including spacing), in dots
+pub(crate) const FONT_A_WIDTH_DOTS: u16 = 12;
+
+// Width of a Font B character (including spacing), in dots
+pub(crate) const FONT_B_WIDTH_DOTS: u16 = 9;
+
+// Horizontal resolution of the TM-T88IV (180 dpi)
+pub(crate) const DOTS_PER_MM: f32 = 180.0 / 25.4;
+
+// Printable width of the TM-T88IV on 80mm paper
+pub const DEFAULT_PRINT_WIDTH_MM: f32 = 72.0;
+
+// Print speed assumed for models without one in `MODEL_CAPS`, on the slow side of thermal
+// receipt printers so that estimates err long
+pub const DEFAULT_PRINT_SPEED_MM_S: f32 = 100.0;
+
+// Default line spacing (`ESC 2`) is 1/6 inch
+pub(crate) const DEFAULT_LINE_HEIGHT_MM: f32 = 25.4 / 6.0;
+
+/// Estimates the length of paper (in millimeters) that printing an `ESC/POS` byte stream feeds,
+/// given the height of a line at the default line spacing.
+///
+/// Commands are decoded with their actual parameters (fixed, length-prefixed or terminated) so
+/// that no parameter or data byte is mistaken for a line feed. Text lines are weighted by the
+/// character height magnification (`GS !`, `ESC !`) that is active when they are terminated,
+/// feeds (`ESC J`, `ESC d`) are added as is, and graphics, barcodes and QR codes add their height
+/// in dots. A trailing line without a line feed is counted as well.
+///
+/// The height of QR codes is approximated from the length of their data, and lines wrapped by
+/// the printer are not accounted for.
+pub(crate) fn estimate_length_mm(bytes: &[u8], line_height_mm: f32) -> f32 {
+    let mut estimate = LengthEstimate::new(line_height_mm);
+    let mut cursor = Cursor { bytes, pos: 0 };
+    while let Some(b) = cursor.byte() {
+        // A command cut short by the end of the stream has nothing more to print
+        if estimate.decode(b, &mut cursor).is_none() {
+            break;
+        }
+    }
+    estimate.finish()
+}
+
+// Reads an `ESC/POS` byte stream, returning `None` past its end
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let taken = self.bytes.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.take(N)?.try_into().ok()
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        self.array().map(|[b]| b)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    // Skips past the next NUL, which terminates the data of some commands
+    fn skip_terminated(&mut self) -> Option<()> {
+        let len = self.bytes.get(self.pos..)?.iter().position(|&b| b == 0)?;
+        self.take(len + 1).map(|_| ())
+    }
+}
+
+// Height of barcodes (`GS h`) and number of lines of their digits (`GS H`) until changed
+const DEFAULT_BARCODE_HEIGHT_DOTS: u8 = 162;
+// Width of QR code modules (`GS ( k` function 167) until changed
+const DEFAULT_QR_MODULE_DOTS: u8 = 3;
+
+// Paper fed so far, and the state of the printer that decides how much the next command feeds
+struct LengthEstimate {
+    mm: f32,
+    default_line_mm: f32,
+    line_mm: f32,
+    h_magnify: f32,
+    has_pending_line: bool,
+    barcode_height: u8,
+    hri_lines: u8,
+    qr_module: u8,
+    qr_error_correction: u8,
+    qr_data_len: usize,
+    // Heights (in dots) of the download and NV graphics defined in the stream, by key
+    graphics: HashMap<(bool, [u8; 2]), u16>,
+    // Height (in dots) of the graphics stored in the print buffer (`GS ( L` function 112)
+    buffered_graphics: u16,
+    // Height (in dots) of the downloaded bit image (`GS *`)
+    bit_image: u16,
+}
+
+impl LengthEstimate {
+    fn new(line_height_mm: f32) -> Self {
+        Self {
+            mm: 0.0,
+            default_line_mm: line_height_mm,
+            line_mm: line_height_mm,
+            h_magnify: 1.0,
+            has_pending_line: false,
+            barcode_height: DEFAULT_BARCODE_HEIGHT_DOTS,
+            hri_lines: 0,
+            qr_module: DEFAULT_QR_MODULE_DOTS,
+            qr_error_correction: QrErrorCorrection::L as u8,
+            qr_data_len: 0,
+            graphics: HashMap::new(),
+            buffered_graphics: 0,
+            bit_image: 0,
+        }
+    }
+
+    fn finish(mut self) -> f32 {
+        self.end_line();
+        self.mm
+    }
+
+    // Prints the pending line, if any
+    fn end_line(&mut self) {
+        if self.has_pending_line {
+            self.line_feed();
+        }
+    }
+
+    fn line_feed(&mut self) {
+        self.mm += self.line_mm * self.h_magnify;
+        self.has_pending_line = false;
+    }
+
+    fn feed_dots(&mut self, dots: f32) {
+        self.mm += dots / DOTS_PER_MM;
+    }
+
+    fn feed_units(&mut self, units: u8) {
+        self.mm += units as f32 / VERTICAL_MOTION_UNITS_PER_INCH as f32 * 25.4;
+    }
+
+    // Raster images, barcodes and QR codes are printed on their own, below the pending line
+    fn print_raster(&mut self, dots: f32) {
+        self.end_line();
+        self.feed_dots(dots);
+    }
+
+    fn decode(&mut self, b: u8, cursor: &mut Cursor) -> Option<()> {
+        match b {
+            ESC => self.decode_esc(cursor),
+            GS => self.decode_gs(cursor),
+            FS => self.decode_fs(cursor),
+            DLE => self.decode_dle(cursor),
+            b'\n' => {
+                self.line_feed();
+                Some(())
+            }
+            b'\r' => Some(()),
+            _ => {
+                self.has_pending_line = true;
+                Some(())
+            }
+        }
+    }
+
+    fn decode_esc(&mut self, cursor: &mut Cursor) -> Option<()> {
+        match cursor.byte()? {
+            b'@' => {
+                let graphics = std::mem::take(&mut self.graphics);
+                *self = Self {
+                    mm: self.mm,
+                    graphics,
+                    ..Self::new(self.default_line_mm)
+                };
+            }
+            b'!' => {
+                let mode = cursor.byte()?;
+                self.h_magnify = if mode & 0x10 != 0 { 2.0 } else { 1.0 };
+            }
+            b'2' => self.line_mm = self.default_line_mm,
+            b'3' => {
+                let units = cursor.byte()?;
+                self.line_mm = units as f32 / VERTICAL_MOTION_UNITS_PER_INCH as f32 * 25.4;
+            }
+            b'J' => {
+                let units = cursor.byte()?;
+                self.has_pending_line = false;
+                self.feed_units(units);
+            }
+            b'd' => {
+                let lines = cursor.byte()?;
+                self.end_line();
+                self.mm += lines as f32 * self.line_mm * self.h_magnify;
+            }
+            b'K' => {
+                let units = cursor.byte()?;
+                self.has_pending_line = false;
+                self.mm -= units as f32 / VERTICAL_MOTION_UNITS_PER_INCH as f32 * 25.4;
+            }
+            b'*' => {
+                // Bit images are printed in line with the text, 8 or 24 dots high
+                let mode = cursor.byte()?;
+                let columns = cursor.u16()? as usize;
+                let bytes_per_column = if mode >= 32 { 3 } else { 1 };
+                cursor.take(columns * bytes_per_column)?;
+                self.has_pending_line = true;
+            }
+            b'&' => {
+                // User-defined characters, `x` columns of `y` bytes for each of `c1..=c2`
+                let [y, c1, c2] = cursor.array()?;
+                for _ in c1..=c2 {
+                    let x = cursor.byte()?;
+                    cursor.take(y as usize * x as usize)?;
+                }
+            }
+            b'D' => cursor.skip_terminated()?,
+            b'W' => {
+                cursor.take(8)?;
+            }
+            b'(' => {
+                let _ = cursor.byte()?;
+                let len = cursor.u16()? as usize;
+                cursor.take(len)?;
+            }
+            b'$' | b'\\' | b'B' | b'c' => {
+                cursor.take(2)?;
+            }
+            b'p' => {
+                cursor.take(3)?;
+            }
+            // Commands without parameters
+            b'<' | b'4' | b'5' | b'F' | b'L' | b'S' | b'i' | b'm' => {}
+            _ => {
+                cursor.byte()?;
+            }
+        }
+        Some(())
+    }
+
+    fn decode_gs(&mut self, cursor: &mut Cursor) -> Option<()> {
+        match cursor.byte()? {
+            b'!' => {
+                let size = cursor.byte()?;
+                self.h_magnify = ((size & 0b111) + 1) as f32;
+            }
+            b'V' => {
+                // `GS V m n` variants with a feed amount carry an extra parameter, by which the
+                // paper is fed before cutting
+                let m = cursor.byte()?;
+                self.end_line();
+                if m >= 65 {
+                    let units = cursor.byte()?;
+                    self.feed_units(units);
+                }
+            }
+            b'h' => self.barcode_height = cursor.byte()?,
+            b'H' => {
+                self.hri_lines = match cursor.byte()? {
+                    1 | 2 | b'1' | b'2' => 1,
+                    3 | b'3' => 2,
+                    _ => 0,
+                };
+            }
+            b'k' => {
+                // Symbologies 0-6 end with a NUL, 65 onwards are prefixed by their length
+                let m = cursor.byte()?;
+                if m <= 6 {
+                    cursor.skip_terminated()?;
+                } else {
+                    let len = cursor.byte()?;
+                    cursor.take(len as usize)?;
+                }
+                let hri_mm = self.hri_lines as f32 * self.line_mm;
+                self.print_raster(self.barcode_height as f32);
+                self.mm += hri_mm;
+            }
+            b'v' => {
+                // `GS v 0 m xL xH yL yH`, where modes 2 and 3 double the height
+                let [_, m] = cursor.array()?;
+                let width = cursor.u16()? as usize;
+                let height = cursor.u16()?;
+                cursor.take(width * height as usize)?;
+                let scale = if m & 2 != 0 { 2 } else { 1 };
+                self.print_raster((height * scale) as f32);
+            }
+            b'*' => {
+                let [x, y] = cursor.array()?;
+                cursor.take(x as usize * y as usize * 8)?;
+                self.bit_image = y as u16 * 8;
+            }
+            b'/' => {
+                let m = cursor.byte()?;
+                let scale = if m & 2 != 0 { 2 } else { 1 };
+                self.print_raster((self.bit_image * scale) as f32);
+            }
+            b'(' => {
+                let function = cursor.byte()?;
+                let len = cursor.u16()? as usize;
+                let params = cursor.take(len)?;
+                match function {
+                    b'k' => self.decode_symbol(params),
+                    b'L' => self.decode_graphics(params),
+                    _ => {}
+                }
+            }
+            b'8' => {
+                // Extended form of `GS ( L` for graphics longer than 65535 bytes
+                let function = cursor.byte()?;
+                let len = u32::from_le_bytes(cursor.array()?) as usize;
+                let params = cursor.take(len)?;
+                if function == b'L' {
+                    self.decode_graphics(params);
+                }
+            }
+            b'D' => {
+                // NV graphics management, `GS D` carries a length like `GS ( L`
+                let _ = cursor.byte()?;
+                let len = cursor.u16()? as usize;
+                cursor.take(len)?;
+            }
+            b'P' | b'\\' | b'$' | b'L' | b'W' => {
+                cursor.take(2)?;
+            }
+            // Commands without parameters
+            b':' => {}
+            _ => {
+                cursor.byte()?;
+            }
+        }
+        Some(())
+    }
+
+    // `GS ( k` with its parameter length already read, of which only QR codes (`cn = 49`) are
+    // tracked
+    fn decode_symbol(&mut self, params: &[u8]) {
+        let [49, function, rest @ ..] = params else {
+            return;
+        };
+        match (function, rest) {
+            (67, [size, ..]) => self.qr_module = *size,
+            (69, [level, ..]) => self.qr_error_correction = *level,
+            (80, [_, data @ ..]) => self.qr_data_len = data.len(),
+            (81, _) if self.qr_data_len > 0 => {
+                let dots = qr_size_modules(self.qr_data_len, self.qr_error_correction)
+                    * self.qr_module as usize;
+                self.print_raster(dots as f32);
+            }
+            _ => {}
+        }
+    }
+
+    // `GS ( L` with its parameter length already read
+    #[allow(non_snake_case)]
+    fn decode_graphics(&mut self, params: &[u8]) {
+        match params {
+            // Functions 67 (NV) and 83 (download): define raster graphics under a key
+            [48, function @ (67 | 83), 48, kc1, kc2, _, _, _, yL, yH, ..] => {
+                let height = u16::from_le_bytes([*yL, *yH]);
+                self.graphics
+                    .insert((*function == 67, [*kc1, *kc2]), height);
+            }
+            // Functions 69 (NV) and 85 (download): print the graphics defined under a key
+            [48, function @ (69 | 85), kc1, kc2, _, y, ..] => {
+                if let Some(&height) = self.graphics.get(&(*function == 69, [*kc1, *kc2])) {
+                    self.print_raster((height * *y as u16) as f32);
+                }
+            }
+            // Function 112: stores raster graphics in the print buffer, `by` times as high
+            [48, 112, _, _, by, _, _, _, yL, yH, ..] => {
+                self.buffered_graphics = u16::from_le_bytes([*yL, *yH]) * *by as u16;
+            }
+            // Function 50: prints the graphics in the print buffer
+            [48, 50, ..] => {
+                let height = std::mem::take(&mut self.buffered_graphics);
+                self.print_raster(height as f32);
+            }
+            _ => {}
+        }
+    }
+
+    fn decode_fs(&mut self, cursor: &mut Cursor) -> Option<()> {
+        match cursor.byte()? {
+            b'(' => {
+                let _ = cursor.byte()?;
+                let len = cursor.u16()? as usize;
+                cursor.take(len)?;
+            }
+            b'p' | b'S' => {
+                cursor.take(2)?;
+            }
+            b'q' => {
+                // Defines `n` NV bit images, each `xL xH yL yH` followed by its data
+                let images = cursor.byte()?;
+                for _ in 0..images {
+                    let x = cursor.u16()? as usize;
+                    let y = cursor.u16()? as usize;
+                    cursor.take(x * y * 8)?;
+                }
+            }
+            // Commands without parameters
+            b'&' | b'.' => {}
+            _ => {
+                cursor.byte()?;
+            }
+        }
+        Some(())
+    }
+
+    fn decode_dle(&mut self, cursor: &mut Cursor) -> Option<()> {
+        match cursor.byte()? {
+            EOT | ENQ => {
+                cursor.byte()?;
+            }
+            DC4 => {
+                let function = cursor.byte()?;
+                let len = match function {
+                    7 => 1,
+                    8 => 7,
+                    _ => 2,
+                };
+                cursor.take(len)?;
+            }
+            // Not a real-time command, `DLE` is then an ordinary character
+            _ => self.has_pending_line = true,
+        }
+        Some(())
+    }
+}
+
+// Width (and height) in modules of a QR code storing `data_len` bytes in byte mode, with error
+// correction `level` (48-51 for L-H). The version is the smallest whose codewords fit the data
+// besides the rough share that error correction takes of them, which is close enough for
+// estimating paper length
+fn qr_size_modules(data_len: usize, level: u8) -> usize {
+    let ec_share = match level {
+        48 => 0.2,
+        49 => 0.38,
+        50 => 0.55,
+        _ => 0.65,
+    };
+    // Mode indicator and a character count of up to 16 bits
+    let data_bits = 4 + 16 + data_len * 8;
+    let version = (1..=40)
+        .find(|&version| {
+            let codewords = qr_raw_modules(version) / 8;
+            codewords as f32 * (1.0 - ec_share) * 8.0 >= data_bits as f32
+        })
+        .unwrap_or(40);
+    17 + 4 * version
+}
+
+// Modules of a QR code `version` that are left for data and error correction, once finder,
+// timing and alignment patterns and format and version information are taken out
+fn qr_raw_modules(version: usize) -> usize {
+    let mut modules = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let alignments = version / 7 + 2;
+        modules -= (25 * alignments - 10) * alignments - 55;
+        if version >= 7 {
+            modules -= 36;
+        }
+    }
+    modules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINE_MM: f32 = DEFAULT_LINE_HEIGHT_MM;
+
+    fn assert_mm(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "estimated {actual} mm, expected {expected} mm"
+        );
+    }
+
+    #[test]
+    fn estimate_counts_lines_and_trailing_line() {
+        assert_mm(
+            estimate_length_mm(b"one\ntwo\nthree", LINE_MM),
+            3.0 * LINE_MM,
+        );
+        assert_mm(estimate_length_mm(b"", LINE_MM), 0.0);
+    }
+
+    #[test]
+    fn estimate_does_not_swallow_line_feed_after_italics() {
+        let mut stream = Vec::new();
+        stream.extend(CMD_ITALIC(true));
+        stream.extend(b"\nslanted\n");
+        stream.extend(CMD_ITALIC(false));
+        stream.extend(b"\nupright\n");
+        assert_mm(estimate_length_mm(&stream, LINE_MM), 4.0 * LINE_MM);
+    }
+
+    #[test]
+    fn estimate_weights_lines_by_height_magnification() {
+        let mut stream = CMD_CHAR_SIZE(3, 1);
+        stream.extend(b"title\n");
+        stream.extend(CMD_CHAR_SIZE(1, 1));
+        stream.extend(b"body\n");
+        assert_mm(estimate_length_mm(&stream, LINE_MM), 4.0 * LINE_MM);
+    }
+
+    #[test]
+    fn estimate_adds_qr_code_height_without_counting_its_data() {
+        let mut stream = CMD_QR_MODEL(QrModel::Model2);
+        stream.extend(CMD_QR_MODULE_SIZE(6));
+        stream.extend(CMD_QR_ERROR_CORRECTION(QrErrorCorrection::M));
+        // Line feeds in the data are not printed lines
+        stream.extend(CMD_QR_STORE(b"\n\nhttps://\n"));
+        stream.extend(CMD_QR_PRINT);
+        stream.extend(b"caption\n");
+
+        // 11 bytes fit a version 1 code (21 modules) at level M
+        let qr_mm = 21.0 * 6.0 / DOTS_PER_MM;
+        assert_mm(estimate_length_mm(&stream, LINE_MM), qr_mm + LINE_MM);
+    }
+
+    #[test]
+    fn estimate_sizes_qr_code_by_data_length_and_error_correction() {
+        assert_eq!(qr_size_modules(10, QrErrorCorrection::L as u8), 21);
+        assert_eq!(qr_size_modules(100, QrErrorCorrection::L as u8), 37);
+        assert!(qr_size_modules(100, QrErrorCorrection::H as u8) > 37);
+        assert_eq!(qr_size_modules(10_000, QrErrorCorrection::H as u8), 177);
+    }
+
+    #[test]
+    fn estimate_adds_graphics_height_without_counting_its_data() {
+        // Every row is made of line feeds
+        let raster = Raster::new(16, 40, vec![b'\n'; 2 * 40]).unwrap();
+        let mut stream = b"above".to_vec();
+        stream.extend(CMD_DEFINE_GRAPHICS(*b"AB", &raster));
+        stream.extend(CMD_PRINT_GRAPHICS(*b"AB", ImageDensity::Quad));
+        stream.extend(CMD_PRINT_GRAPHICS(*b"AB", ImageDensity::DoubleW));
+
+        let graphics_mm = (80.0 + 40.0) / DOTS_PER_MM;
+        assert_mm(estimate_length_mm(&stream, LINE_MM), LINE_MM + graphics_mm);
+    }
+
+    #[test]
+    fn estimate_ignores_graphics_printed_under_unknown_key() {
+        let stream = CMD_PRINT_GRAPHICS(*b"ZZ", ImageDensity::Normal);
+        assert_mm(estimate_length_mm(&stream, LINE_MM), 0.0);
+    }
+
+    #[test]
+    fn estimate_keeps_bit_images_in_line() {
+        // A glyph of 2 columns whose bytes are line feeds
+        let mut stream = b"a".to_vec();
+        stream.extend(CMD_BIT_IMAGE_24(&[b'\n'; 6]));
+        stream.extend(b"b\n");
+        assert_mm(estimate_length_mm(&stream, LINE_MM), LINE_MM);
+    }
+
+    #[test]
+    fn estimate_adds_barcode_and_digits() {
+        let mut stream = CMD_BARCODE_HRI_BELOW.to_vec();
+        stream.extend(CMD_BARCODE(Symbology::Ean13, b"0101010101010"));
+        let barcode_mm = DEFAULT_BARCODE_HEIGHT_DOTS as f32 / DOTS_PER_MM;
+        assert_mm(estimate_length_mm(&stream, LINE_MM), barcode_mm + LINE_MM);
+    }
+
+    #[test]
+    fn estimate_adds_feeds() {
+        let mut stream = CMD_FEED_LINES(2);
+        stream.extend(CMD_FEED_MM(10.0, VERTICAL_MOTION_UNITS_PER_INCH));
+        stream.extend(CMD_CUT_FEED(10));
+        let units_to_mm = |units: u32| units as f32 / VERTICAL_MOTION_UNITS_PER_INCH as f32 * 25.4;
+        let feed_mm = units_to_mm(mm_to_motion_units(10.0, VERTICAL_MOTION_UNITS_PER_INCH));
+        assert_mm(
+            estimate_length_mm(&stream, LINE_MM),
+            2.0 * LINE_MM + feed_mm + units_to_mm(10),
+        );
+    }
+
+    #[test]
+    fn estimate_skips_real_time_commands() {
+        let mut stream = CMD_RT_CLEAR_BUFFER.to_vec();
+        stream.extend(CMD_RT_GENERATE_PULSE(DrawerPin::Pin2, 10));
+        stream.extend(CMD_RT_STATUS(RtStatusReq::PrinterStatus));
+        assert_mm(estimate_length_mm(&stream, LINE_MM), 0.0);
+    }
+
+    #[test]
+    fn transmit_status_cmd_encodes_request() {
+        assert_eq!(
+            CMD_TRANSMIT_STATUS(TransmitStatusReq::PaperSensor),
+            [GS, b'r', 1]
+        );
+        assert_eq!(
+            CMD_TRANSMIT_STATUS(TransmitStatusReq::Drawer),
+            [GS, b'r', 2]
+        );
+    }
+
+    #[test]
+    fn transmit_paper_status_masks() {
+        let parse = PaperStatus::from_transmit_byte;
+        assert_eq!(parse(0b0000_0000), Some(PaperStatus::Adequate));
+        // Near-end sensor, bits 0-1
+        assert_eq!(parse(0b0000_0011), Some(PaperStatus::NearEnd));
+        // Roll end sensor, bits 2-3, which takes priority over the near-end sensor
+        assert_eq!(parse(0b0000_1100), Some(PaperStatus::NotPresent));
+        assert_eq!(parse(0b0000_1111), Some(PaperStatus::NotPresent));
+        // Bits 4 and 7 are always unset in a `GS r` reply
+        assert_eq!(parse(0b0001_0000), None);
+        assert_eq!(parse(0b1000_0000), None);
+    }
+
+    #[test]
+    fn transmit_drawer_status_masks() {
+        assert_eq!(drawer_pin_high_from_transmit_byte(0b0000_0001), Some(true));
+        assert_eq!(drawer_pin_high_from_transmit_byte(0b0000_0000), Some(false));
+        assert_eq!(drawer_pin_high_from_transmit_byte(0b1001_0001), None);
+    }
+
+    #[test]
+    fn transmit_status_byte_skips_asb() {
+        assert_eq!(transmit_status_byte(&[0b0000_0011]), Some(0b0000_0011));
+        assert_eq!(
+            transmit_status_byte(&[0x10, 0x00, 0x00, 0x00, 0b0000_1100]),
+            Some(0b0000_1100)
+        );
+        assert_eq!(transmit_status_byte(&[0x10, 0x00, 0x00, 0x00]), None);
+        assert_eq!(transmit_status_byte(&[]), None);
+    }
+
+    // Replies to `DLE EOT n=1-4` of a printer that went offline after an error it recovers
+    // from on its own, and is now waiting to go back online
+    const WAITING_RECOVERY_BYTES: [u8; 4] = [0b0011_1010, 0b0101_0010, 0b0101_0010, 0b0001_0010];
+
+    #[test]
+    fn error_cause_reports_waiting_for_recovery() {
+        let status = PrinterStatus::from_bytes(&WAITING_RECOVERY_BYTES).unwrap();
+        assert!(!status.is_online());
+        let error = status.offline_cause().unwrap().error().unwrap();
+        // `DLE EOT n=1`, bit 5
+        assert!(error.is_waiting_recovery());
+        // `DLE EOT n=3`, bit 6
+        assert!(error.is_recoverable_err());
+        assert!(!error.is_fatal_err());
+        assert!(!error.is_cutter_err());
+        assert_eq!(error.to_string(), "auto-recovery, waiting-recovery");
+    }
+
+    #[test]
+    fn error_cause_serializes_waiting_for_recovery() {
+        let status = PrinterStatus::from_bytes(&WAITING_RECOVERY_BYTES).unwrap();
+        let json = serde_json::to_value(&status).unwrap();
+        let error = &json["offline_cause"]["error"];
+        assert_eq!(error["is_waiting_recovery"], true);
+        assert_eq!(error["is_recoverable_err"], true);
+        assert_eq!(error["is_fatal_err"], false);
+    }
+
+    #[test]
+    fn error_cause_without_waiting_for_recovery() {
+        // Cutter error, not recovered yet
+        let bytes = [0b0001_1010, 0b0101_0010, 0b0001_1010, 0b0001_0010];
+        let status = PrinterStatus::from_bytes(&bytes).unwrap();
+        let error = status.offline_cause().unwrap().error().unwrap();
+        assert!(!error.is_waiting_recovery());
+        assert!(error.is_cutter_err());
+        assert_eq!(error.to_string(), "cutter-error");
+    }
+
+    #[test]
+    fn abs_position_encodes_dots_little_endian() {
+        assert_eq!(CMD_ABS_POSITION(0), [ESC, b'$', 0x00, 0x00]);
+        assert_eq!(CMD_ABS_POSITION(0x0168), [ESC, b'$', 0x68, 0x01]);
+        assert_eq!(CMD_ABS_POSITION(u16::MAX), [ESC, b'$', 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn mm_converts_to_motion_units() {
+        // 1/360 inch units (TM-T88IV default)
+        assert_eq!(mm_to_motion_units(5.0, 360), 71);
+        assert_eq!(mm_to_motion_units(25.4, 360), 360);
+        // 1/180 inch units
+        assert_eq!(mm_to_motion_units(5.0, 180), 35);
+        assert_eq!(mm_to_motion_units(25.4, 180), 180);
+        assert_eq!(mm_to_motion_units(-1.0, 360), 0);
+    }
+
+    #[test]
+    fn feed_mm_splits_into_255_unit_feeds() {
+        assert_eq!(CMD_FEED_MM(5.0, 360), [ESC, b'J', 71]);
+        assert_eq!(CMD_FEED_MM(5.0, 180), [ESC, b'J', 35]);
+        // 20mm is 283 units at 1/360 inch
+        assert_eq!(CMD_FEED_MM(20.0, 360), [ESC, b'J', 255, ESC, b'J', 28]);
+        assert!(CMD_FEED_MM(0.0, 360).is_empty());
+    }
+
+    #[test]
+    fn char_size_encodes_magnification_minus_one() {
+        assert_eq!(CMD_CHAR_SIZE(1, 1), [GS, b'!', 0x00]);
+        assert_eq!(CMD_CHAR_SIZE(8, 8), [GS, b'!', 0x77]);
+        // Height in the low nibble, width in the high one
+        assert_eq!(CMD_CHAR_SIZE(2, 1), [GS, b'!', 0x01]);
+        assert_eq!(CMD_CHAR_SIZE(1, 2), [GS, b'!', 0x10]);
+    }
+
+    #[test]
+    fn char_size_clamps_magnification() {
+        assert_eq!(CMD_CHAR_SIZE(0, 0), CMD_CHAR_SIZE(1, 1));
+        assert_eq!(CMD_CHAR_SIZE(9, u8::MAX), CMD_CHAR_SIZE(8, 8));
+    }
+
+    #[test]
+    fn print_graphics_encodes_density_as_dot_scale() {
+        let header = [GS, b'(', b'L', 6, 0, 48, 85, b'A', b'B'];
+        for (density, scale) in [
+            (ImageDensity::Normal, [1, 1]),
+            (ImageDensity::DoubleW, [2, 1]),
+            (ImageDensity::DoubleH, [1, 2]),
+            (ImageDensity::Quad, [2, 2]),
+        ] {
+            assert_eq!(
+                CMD_PRINT_GRAPHICS(*b"AB", density),
+                [&header[..], &scale].concat()
+            );
+        }
+    }
+
+    // Status of an online printer whose `DLE EOT n=4` reply is `paper_status_b`
+    fn status_with_paper(paper_status_b: u8) -> PrinterStatus {
+        let bytes = [0b0001_0010, 0b0001_0010, 0b0001_0010, paper_status_b];
+        PrinterStatus::from_bytes(&bytes).unwrap()
+    }
+
+    fn paper_status(paper_status_b: u8) -> PaperStatus {
+        status_with_paper(paper_status_b).paper_status
+    }
+
+    #[test]
+    fn paper_status_masks() {
+        assert_eq!(paper_status(0b0001_0010), PaperStatus::Adequate);
+        // Near-end sensor, bits 2-3
+        assert_eq!(paper_status(0b0001_1110), PaperStatus::NearEnd);
+        // Roll end sensor, bits 5-6, which takes priority over the near-end sensor
+        assert_eq!(paper_status(0b0111_0010), PaperStatus::NotPresent);
+        assert_eq!(paper_status(0b0111_1110), PaperStatus::NotPresent);
+    }
+
+    #[test]
+    fn near_end_is_adequate_without_near_end_sensor() {
+        // With the sensor, near-end is reported as is
+        assert_eq!(paper_status(0b0001_1110), PaperStatus::NearEnd);
+        // Without it, a (spurious) near-end is adequate, while the end of the roll still counts
+        let ignoring = |paper_status_b| {
+            status_with_paper(paper_status_b)
+                .ignoring_near_end()
+                .paper_status
+        };
+        assert_eq!(ignoring(0b0001_1110), PaperStatus::Adequate);
+        assert_eq!(ignoring(0b0001_0010), PaperStatus::Adequate);
+        assert_eq!(ignoring(0b0111_1110), PaperStatus::NotPresent);
+    }
+
+    #[test]
+    fn cut_feed_converts_to_motion_units() {
+        assert_eq!(cut_feed_units(0.0, 360), 0);
+        // 3mm is 42.5 units at 1/360 inch, and 21.3 at 1/180 inch
+        assert_eq!(cut_feed_units(3.0, 360), 43);
+        assert_eq!(cut_feed_units(3.0, 180), 21);
+        assert_eq!(cut_feed_units(MAX_CUT_FEED_MM, 360), u8::MAX);
+        // Longer feeds are clamped to what `n` can hold
+        assert_eq!(cut_feed_units(50.0, 360), u8::MAX);
+    }
+
+    #[test]
+    fn rotate_90_encodes_on_and_off() {
+        assert_eq!(CMD_ROTATE_90(true), [ESC, b'V', 1]);
+        assert_eq!(CMD_ROTATE_90(false), [ESC, b'V', 0]);
+    }
+
+    // An `ASB` message whose bytes after the header look like `DLE EOT` replies
+    const ASB_LIKE_REPLIES: [u8; 4] = [0b0001_0000, 0b0001_0010, 0b0001_0010, 0b0001_0010];
+
+    #[test]
+    fn rt_status_byte_takes_reply_before_asb() {
+        // Stale replies, the reply, then the `ASB` message in bytes 4-7
+        let reply = [&[0x12, 0x12, 0x12, 0b0001_0110][..], &ASB_LIKE_REPLIES].concat();
+        assert_eq!(reply.len(), 8);
+        assert_eq!(rt_status_byte(&reply), Some(0b0001_0110));
+    }
+
+    #[test]
+    fn rt_status_byte_without_reply() {
+        assert_eq!(rt_status_byte(&ASB_LIKE_REPLIES), None);
+        assert_eq!(rt_status_byte(&[0x00, 0xFF]), None);
+        assert_eq!(rt_status_byte(&[]), None);
+    }
+
+    // Error of a printer that is offline with an error cause of `err_cause_b` (`DLE EOT n=3`)
+    fn error_with_cause(err_cause_b: u8) -> PrinterError {
+        let bytes = [0b0001_1010, 0b0101_0010, err_cause_b, 0b0001_0010];
+        let status = PrinterStatus::from_bytes(&bytes).unwrap();
+        status.offline_cause().unwrap().error().unwrap().clone()
+    }
+
+    #[test]
+    fn head_temperature_error_is_auto_recoverable() {
+        // Bit 6
+        let error = error_with_cause(0b0101_0010);
+        assert!(error.is_recoverable_err());
+        assert!(!error.is_fatal_err());
+        assert_eq!(error.to_string(), "auto-recovery");
+
+        let json = serde_json::to_value(&error).unwrap();
+        assert_eq!(json["is_recoverable_err"], true);
+        assert_eq!(json["is_fatal_err"], false);
+    }
+
+    #[test]
+    fn voltage_error_is_fatal() {
+        // Bit 5
+        let error = error_with_cause(0b0011_0010);
+        assert!(error.is_fatal_err());
+        assert!(!error.is_recoverable_err());
+        assert_eq!(error.to_string(), "fatal-error");
+
+        let json = serde_json::to_value(&error).unwrap();
+        assert_eq!(json["is_fatal_err"], true);
+        assert_eq!(json["is_recoverable_err"], false);
+    }
+
+    #[test]
+    fn check_digit_of_known_barcodes() {
+        // EAN-13 4006381333931
+        assert_eq!(check_digit(b"400638133393"), b'1');
+        // UPC-A 036000291452
+        assert_eq!(check_digit(b"03600029145"), b'2');
+        // EAN-8 96385074
+        assert_eq!(check_digit(b"9638507"), b'4');
+    }
+
+    #[test]
+    fn status_accepts_each_subcommand_reply() {
+        // Replies with every variable bit of their subcommand set, besides bits 0-1, 4 and 7
+        let bytes = [
+            // n=1: drawer pin 3 high, offline, waiting for recovery, feed button pressed
+            0b0111_1110,
+            // n=2: cover open, fed by button, stopped at paper end, error
+            0b0111_1110,
+            // n=3: recoverable, cutter, unrecoverable and auto-recoverable errors
+            0b0111_1110,
+            // n=4: paper near its end and out
+            0b0111_1110,
+        ];
+        let status = PrinterStatus::from_bytes(&bytes).unwrap();
+        assert!(!status.is_online());
+        assert_eq!(*status.paper_status(), PaperStatus::NotPresent);
+        let cause = status.offline_cause().unwrap();
+        assert!(cause.is_cover_open() && cause.is_paper_empty());
+        let error = cause.error().unwrap();
+        assert!(error.is_cutter_err() && error.is_fatal_err() && error.is_recoverable_err());
+    }
+
+    #[test]
+    fn status_of_ready_printer() {
+        // As read from a TM-T88IV with its drawer closed
+        let status = PrinterStatus::from_bytes(&[0x16, 0x12, 0x12, 0x12]).unwrap();
+        assert!(status.is_ready());
+        assert!(status.offline_cause().is_none());
+        assert_eq!(*status.paper_status(), PaperStatus::Adequate);
+    }
+
+    #[test]
+    fn status_rejects_bytes_breaking_fixed_bits() {
+        let valid = [0x16, 0x12, 0x12, 0x12];
+        for (idx, fixed_bit) in [0b0000_0001, 0b0000_0010, 0b0001_0000, 0b1000_0000]
+            .into_iter()
+            .enumerate()
+        {
+            let mut bytes = valid;
+            bytes[idx] ^= fixed_bit;
+            assert!(PrinterStatus::from_bytes(&bytes).is_none(), "{bytes:02x?}");
+        }
+    }
+
+    #[test]
+    fn tm_t88iv_feeds_paper_backwards() {
+        let caps = Capabilities::from_model_id(0x20);
+        assert_eq!(caps.supports(Feature::ReverseFeed), Some(true));
+        assert_eq!(
+            Capabilities::from_model_id(0xFF).supports(Feature::ReverseFeed),
+            None
+        );
+    }
+
+    #[test]
+    fn tm_t88iv_lacks_italics() {
+        let caps = Capabilities::from_model_id(0x20);
+        assert_eq!(caps.supports(Feature::Italic), Some(false));
+        assert_eq!(CMD_ITALIC(true), [ESC, b'4']);
+        assert_eq!(CMD_ITALIC(false), [ESC, b'5']);
+    }
+}
@@ -0,0 +1,100 @@
+//! Front matter of markdown documents, i.e. a leading block of `key: value` settings between
+//! `---` lines, which keeps the formatting of a document with it:
+//!
+//! ```text
+//! ---
+//! align: center
+//! cols: 32
+//! ---
+//! # Receipt
+//! ```
+//!
+//! Only flat `key: value` pairs are understood (values may be quoted), not YAML in general.
+
+use tracing::warn;
+
+use crate::printer::{Align, PrinterConfig, PrintyError, PrintyResult};
+
+const DELIMITER: &str = "---";
+
+/// Settings of a document, each overriding the corresponding [`PrinterConfig`] field if set
+#[derive(Debug, Clone, Default)]
+pub struct FrontMatter {
+    pub align: Option<Align>,
+    pub columns: Option<usize>,
+}
+
+impl FrontMatter {
+    /// Overrides the fields of `config` that are set in the front matter
+    pub fn apply(&self, config: &mut PrinterConfig) {
+        if let Some(align) = self.align {
+            config.align = align;
+        }
+        if let Some(columns) = self.columns {
+            config.columns = columns;
+        }
+    }
+
+    /// Parses a front matter block, as split off by [`split`]. Unsupported settings are
+    /// ignored with a warning.
+    pub fn parse(block: &str) -> PrintyResult<Self> {
+        let mut front_matter = Self::default();
+        for line in block.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parse_err = |context: String| PrintyError::Parse {
+                context: format!("Invalid front matter `{line}`: {context}"),
+                source: None,
+            };
+
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| parse_err("expected `key: value`".to_string()))?;
+            let value = unquote(value.trim());
+            match key.trim() {
+                "align" => {
+                    front_matter.align =
+                        Some(clap::ValueEnum::from_str(value, true).map_err(parse_err)?);
+                }
+                "cols" => match value.parse::<usize>() {
+                    Ok(0) => return Err(parse_err("columns must be at least 1".to_string())),
+                    Ok(cols) => front_matter.columns = Some(cols),
+                    Err(e) => return Err(parse_err(e.to_string())),
+                },
+                key => warn!("Ignoring unsupported front matter setting `{key}`"),
+            }
+        }
+        Ok(front_matter)
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    ['"', '\'']
+        .iter()
+        .find_map(|&quote| value.strip_prefix(quote)?.strip_suffix(quote))
+        .unwrap_or(value)
+}
+
+/// Splits the front matter block (without its `---` delimiters) off the start of `md`,
+/// returning it along with the rest of the document.
+///
+/// Without a closing `---` line, a leading `---` is a thematic break rather than the start of
+/// a front matter, so the document is returned as-is.
+pub fn split(md: &str) -> (Option<&str>, &str) {
+    let mut lines = md.split_inclusive('\n');
+    let block_start = match lines.next() {
+        Some(first_line) if first_line.trim_end() == DELIMITER => first_line.len(),
+        _ => return (None, md),
+    };
+
+    let mut offset = block_start;
+    for line in lines {
+        if line.trim_end() == DELIMITER {
+            return (Some(&md[block_start..offset]), &md[offset + line.len()..]);
+        }
+        offset += line.len();
+    }
+    (None, md)
+}
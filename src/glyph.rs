@@ -0,0 +1,110 @@
+//! Drawing of characters that the printer's code page lacks (e.g. emoji) as inline bit images,
+//! using a TrueType/OpenType font.
+//!
+//! Only outline glyphs are drawn, so color emoji fonts (bitmap glyphs) won't do; a monochrome
+//! one such as Noto Emoji or Symbola will.
+
+use std::{fmt, path::Path};
+
+use crate::codepage::{CodePage, GlyphFallback};
+use crate::escpos::{BIT_IMAGE_24_HEIGHT_DOTS, CMD_BIT_IMAGE_24};
+use crate::printer::{PrintyError, PrintyResult};
+
+// Coverage (0-255) from which a pixel of an anti-aliased glyph is printed as a dot
+const COVERAGE_THRESHOLD: u8 = 128;
+
+/// Font that uncovered characters are drawn with, at the height of a line of text
+pub struct GlyphFont {
+    font: fontdue::Font,
+}
+
+impl fmt::Debug for GlyphFont {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GlyphFont")
+            .field("name", &self.font.name())
+            .field("glyphs", &self.font.glyph_count())
+            .finish()
+    }
+}
+
+impl GlyphFont {
+    pub fn from_bytes(font: &[u8]) -> PrintyResult<Self> {
+        fontdue::Font::from_bytes(font, fontdue::FontSettings::default())
+            .map(|font| Self { font })
+            .map_err(|e| PrintyError::Parse {
+                context: format!("Invalid font: {e}"),
+                source: None,
+            })
+    }
+
+    pub fn from_file(path: &Path) -> PrintyResult<Self> {
+        let font = std::fs::read(path).map_err(|e| PrintyError::Parse {
+            context: format!("Failed to read font {}", path.display()),
+            source: Some(Box::new(e)),
+        })?;
+        Self::from_bytes(&font)
+    }
+
+    /// Renders `ch` as an `ESC *` bit image, or `None` if the font lacks it
+    pub(crate) fn render(&self, ch: char) -> Option<Vec<u8>> {
+        if self.font.lookup_glyph_index(ch) == 0 {
+            return None;
+        }
+
+        // Scale the font so that its ascent and descent span the height of the image
+        let height = BIT_IMAGE_24_HEIGHT_DOTS as f32;
+        let (px, ascent) = match self.font.horizontal_line_metrics(height) {
+            Some(line) if line.ascent > line.descent => {
+                let scale = height / (line.ascent - line.descent);
+                (height * scale, line.ascent * scale)
+            }
+            _ => (height, height * 0.8),
+        };
+        let (metrics, coverage) = self.font.rasterize(ch, px);
+
+        let x_offset = metrics.xmin.max(0) as usize;
+        let width = (metrics.advance_width.round() as usize)
+            .max(x_offset + metrics.width)
+            .max(1);
+        // Row of the image that the top of the glyph's bitmap is on, which is negative if it
+        // rises above the ascent
+        let top = ascent.round() as i32 - metrics.ymin - metrics.height as i32;
+
+        // Columns of 3 bytes, the top dot of each being the most significant bit
+        let bytes_per_column = BIT_IMAGE_24_HEIGHT_DOTS / 8;
+        let mut columns = vec![0u8; width * bytes_per_column];
+        for (idx, &value) in coverage.iter().enumerate() {
+            if value < COVERAGE_THRESHOLD {
+                continue;
+            }
+            let (row, col) = (idx / metrics.width, idx % metrics.width);
+            let Ok(y) = usize::try_from(top + row as i32) else {
+                continue;
+            };
+            if y < BIT_IMAGE_24_HEIGHT_DOTS {
+                columns[(x_offset + col) * bytes_per_column + y / 8] |= 0x80 >> (y % 8);
+            }
+        }
+        Some(CMD_BIT_IMAGE_24(&columns))
+    }
+}
+
+/// Encodes `text` into `page`, handling the characters it lacks according to `fallback`
+pub(crate) fn encode(
+    page: CodePage,
+    text: &str,
+    fallback: GlyphFallback,
+    font: Option<&GlyphFont>,
+) -> Vec<u8> {
+    page.encode_with(text, |ch, buf| match fallback {
+        GlyphFallback::Question => buf.push(b'?'),
+        GlyphFallback::Skip => {}
+        // Zero width joiners and variation selectors (e.g. of `❤️`) only alter how the
+        // characters around them are drawn
+        GlyphFallback::Raster if ch == '\u{200D}' || ('\u{FE00}'..='\u{FE0F}').contains(&ch) => {}
+        GlyphFallback::Raster => match font.and_then(|font| font.render(ch)) {
+            Some(image) => buf.extend(image),
+            None => buf.push(b'?'),
+        },
+    })
+}
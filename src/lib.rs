@@ -0,0 +1,11 @@
+#[cfg(feature = "async")]
+pub mod async_printer;
+pub mod codepage;
+pub mod commands;
+pub mod devices;
+pub mod escpos;
+pub mod front_matter;
+pub mod glyph;
+pub mod printer;
+pub mod raster;
+pub mod template;
@@ -1,12 +1,37 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError},
+    },
+    thread::{self, sleep},
+    time::Duration,
+};
 
-use clap::{Parser, Subcommand};
-use printer::PrintyResult;
-
-use crate::printer::Printer;
-
-mod escpos;
-mod printer;
+use clap::{Parser, Subcommand, ValueEnum};
+use printy::codepage::{CodePage, Encoding, GlyphFallback};
+use printy::devices::Printers;
+use printy::escpos::{
+    DEFAULT_COLUMNS, DEFAULT_FONT_B_COLUMNS, DEFAULT_PRINT_WIDTH_MM, DrawerPin, Feature,
+    ImageDensity, MAX_CUT_FEED_MM, PaperStatus, PrinterStatus, QrErrorCorrection, QrModel,
+    Symbology,
+};
+use printy::front_matter::{self, FrontMatter};
+use printy::glyph::GlyphFont;
+use printy::printer::{
+    Align, BlockquoteStyle, DEFAULT_CONNECT_TIMEOUT_MS, DEFAULT_NETWORK_PORT,
+    DEFAULT_NETWORK_TIMEOUT_MS, Driver, DriverKind, ErrorKind, ItalicStyle, LineEnding,
+    MAX_CHUNK_SIZE, NetworkOptions, Printer, PrinterConfig, PrintyError, PrintyResult, RetryPolicy,
+    StatusMethod, StrongStyle, Transport, UsbOptions, parse_hex, parse_md,
+};
+use printy::raster::Raster;
+use printy::template;
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
 
 #[derive(Parser)]
 #[command(about = r"
@@ -21,39 +46,1088 @@ mod printer;
 struct Cli {
     #[arg(
         long = "vid",
-        default_value_t = 0x4b8,
-        help = "Defaults to VID of Espon TM-T88IV"
+        help = "Defaults to VID of Espon TM-T88IV, overrides that of --printer"
     )]
-    usb_vendor_id: u16,
+    usb_vendor_id: Option<u16>,
     #[arg(
         long = "pid",
-        default_value_t = 0x202,
-        help = "Defaults to PID of Espon TM-T88IV"
+        help = "Defaults to PID of Espon TM-T88IV, overrides that of --printer"
+    )]
+    usb_product_id: Option<u16>,
+    #[arg(
+        long,
+        help = "USB bus number of the printer, to tell apart printers with the same VID/PID (see `lsusb`)"
+    )]
+    bus: Option<u8>,
+    #[arg(
+        long,
+        help = "USB device address of the printer on its bus, to tell apart printers with the same VID/PID"
     )]
-    usb_product_id: u16,
+    address: Option<u8>,
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Select a printer by its name in ~/.config/printy/printers.toml, e.g. `[printers.kitchen]` with `vid` and `pid`, or `host`"
+    )]
+    printer: Option<String>,
+    #[arg(
+        long,
+        value_name = "HOST[:PORT]",
+        conflicts_with = "debug",
+        help = "Connect to a network printer instead, on port 9100 unless given"
+    )]
+    host: Option<String>,
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = DEFAULT_CONNECT_TIMEOUT_MS,
+        help = "How long to wait for a network printer to accept the connection (with --host)"
+    )]
+    connect_timeout_ms: u64,
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = DEFAULT_NETWORK_TIMEOUT_MS,
+        help = "How long each read or write to a network printer may take (with --host)"
+    )]
+    timeout_ms: u64,
     #[arg(long, short, default_value_t = false)]
     debug: bool,
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Report a failure to connect as JSON on stderr, e.g. {\"error\": \"...\", \"kind\": \"device_not_found\"}, the status polled by `status --interval` as one JSON object per line, and the `print --report` summary"
+    )]
+    json: bool,
+    #[arg(
+        long,
+        value_name = "COLUMNS",
+        help = "Width of the debug driver's hex dump, defaults to the terminal width"
+    )]
+    hex_width: Option<usize>,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Also append all bytes sent to the printer to PATH, e.g. to keep a log of each receipt"
+    )]
+    tee: Option<PathBuf>,
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "tee",
+        help = "Abort when writing to the --tee file fails, instead of only warning"
+    )]
+    tee_strict: bool,
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Keep Automatic Status Back (ASB) enabled after init"
+    )]
+    keep_asb: bool,
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Skip draining pending printer output before init"
+    )]
+    no_drain: bool,
+    #[arg(
+        long,
+        value_name = "HEX",
+        value_parser = parse_init_hex,
+        help = "Replace the init sequence (ESC @), e.g. \"1b 40 1b 74 00\". A bad sequence can leave the printer unresponsive"
+    )]
+    // `Vec` is fully qualified so that clap parses a single value rather than a list of `u8`
+    init_hex: Option<std::vec::Vec<u8>>,
+    #[arg(
+        long,
+        value_name = "HEX",
+        value_parser = parse_cut_hex,
+        help = "Replace the cut sequence (GS V), e.g. \"1b 69\" (ESC i, full cut) or \"1b 6d\" (ESC m, partial cut) on clones"
+    )]
+    cut_hex: Option<std::vec::Vec<u8>>,
+    #[arg(
+        long,
+        value_name = "MM",
+        value_parser = parse_cut_feed_mm,
+        conflicts_with = "cut_hex",
+        help = "Paper to feed past the cutting position as part of the cut (GS V 66 n), so the last line clears the cutter"
+    )]
+    cut_feed: Option<f32>,
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["cut_hex", "cut_feed"],
+        help = "Never cut the paper, e.g. to tear it off by hand or to print several jobs on one slip"
+    )]
+    no_cut: bool,
+    #[arg(
+        long,
+        value_parser = parse_chunk_size,
+        help = "Size of each USB bulk write in bytes [default: max packet size of the endpoint]"
+    )]
+    chunk_size: Option<usize>,
+    #[arg(
+        long,
+        default_value_t = RetryPolicy::default().max_attempts - 1,
+        help = "Times to retry USB transfers that stall or time out, or to reconnect to a network printer that dropped the connection mid-job (which may print part of it twice)"
+    )]
+    max_retries: u32,
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = RetryPolicy::default().backoff.as_millis() as u64,
+        help = "Delay before the first retry, doubling with every further retry"
+    )]
+    retry_backoff_ms: u64,
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 0,
+        help = "Wait up to MS for the printer to be plugged in, instead of failing right away"
+    )]
+    wait_for_device: u64,
+    #[arg(
+        long,
+        value_parser = parse_columns,
+        visible_alias = "font-a-cols",
+        help = "Characters per line in the default Font A [default: 42, 30 on 58mm paper], `auto` derives them from the print width of the printer model. Overrides `cols` in markdown front matter"
+    )]
+    cols: Option<Columns>,
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Characters per line in the smaller Font B, used by `--blockquote-style small` [default: 56, 40 on 58mm paper, or derived from the printer model with `--cols auto`]"
+    )]
+    font_b_cols: Option<u32>,
+    #[arg(
+        long,
+        allow_hyphen_values = true,
+        value_parser = clap::value_parser!(i8).range(-6..=6),
+        help = "Set the print density from -6 (lightest) to 6 (darkest), persisted by the printer"
+    )]
+    density: Option<i8>,
+    #[arg(
+        long,
+        value_name = "MM",
+        value_parser = parse_feed_mm,
+        default_value_t = 0.0,
+        help = "Extra paper to feed before each cut, in millimeters"
+    )]
+    feed_mm: f32,
+    #[arg(
+        long,
+        value_name = "MM",
+        value_parser = parse_max_width_mm,
+        default_value_t = DEFAULT_PRINT_WIDTH_MM,
+        help = "Printable width of the paper, which graphics and separators are fit within"
+    )]
+    max_width_mm: f32,
+    #[arg(long, value_enum, default_value_t = StatusMethod::DleEot)]
+    status_method: StatusMethod,
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Treat paper near its end as adequate, for printers without a near-end sensor that report it spuriously"
+    )]
+    ignore_near_end: bool,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Encoding::Utf8,
+        help = "Transcode text into a code page, `auto` picks one that covers the text. Markdown can switch it midway with `<!-- codepage:cp1252 -->` directives"
+    )]
+    encoding: Encoding,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = GlyphFallback::Question,
+        help = "What to print in place of characters the code page lacks, `raster` draws them with --glyph-font"
+    )]
+    glyph_fallback: GlyphFallback,
+    #[arg(
+        long,
+        value_name = "FONT",
+        required_if_eq("glyph_fallback", "raster"),
+        help = "TrueType/OpenType font to draw characters with for `--glyph-fallback raster`, e.g. a monochrome emoji font"
+    )]
+    glyph_font: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Markdown file printed before each job (e.g. the shop's name and address), with the same settings"
+    )]
+    header: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Markdown file printed after each job, before the cut (e.g. a thank you note)"
+    )]
+    footer: Option<PathBuf>,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = QrModel::Model2,
+        help = "QR code model, model 1 is for older scanners that cannot read model 2"
+    )]
+    qr_model: QrModel,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = ItalicStyleArg::Underline,
+        help = "How markdown emphasis is printed, `auto` uses italics if the printer model supports them"
+    )]
+    italic_style: ItalicStyleArg,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = StrongStyle::Bold,
+        help = "How markdown strong emphasis is printed, `reverse` stands out more on thermal paper"
+    )]
+    strong_style: StrongStyle,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = BlockquoteStyle::Indent,
+        help = "How markdown blockquotes are set apart, `small` prints them in the smaller Font B"
+    )]
+    blockquote_style: BlockquoteStyle,
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Print markdown headings twice, offset by a dot, for a heavier style on faded paper. Needs a printer that can feed paper backwards"
+    )]
+    shadow_headings: bool,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = ImageDensity::Normal,
+        help = "Print graphics at double width and/or height, e.g. to fill the paper with a small logo"
+    )]
+    image_density: ImageDensity,
+    #[arg(
+        long,
+        short,
+        default_value_t = false,
+        help = "Log debug diagnostics (e.g. each command sent), unless RUST_LOG is set"
+    )]
+    verbose: bool,
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum ItalicStyleArg {
+    Italic,
+    Underline,
+    Auto,
+}
+
+#[derive(Clone, Copy)]
+enum Columns {
+    Auto,
+    Fixed(usize),
+}
+
+// USB IDs of the Epson TM-T88IV, which this tool is developed against
+const DEFAULT_VID: u16 = 0x4b8;
+const DEFAULT_PID: u16 = 0x202;
+
+const WATCH_INTERVAL_MS: u64 = 1000;
+
+const LOGO_GRAPHICS_KEY: [u8; 2] = *b"LG";
+
+// Distinct beep patterns, so that a failed job can be told apart from across the room
+const JOB_DONE_BEEPS: u8 = 1;
+const JOB_ERROR_BEEPS: u8 = 3;
+// Sounded by `status --watch --alert beep` when the printer stops being ready
+const WATCH_ALERT_BEEPS: u8 = 3;
+
+// Exit code of a job interrupted by Ctrl-C, 128 + SIGINT as shells report it
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Alert {
+    /// Sound the printer's buzzer
+    Beep,
+    /// Ring the terminal bell
+    Bell,
+    None,
+}
+
 #[derive(Subcommand)]
 enum Commands {
-    Status,
-    Print { file: PathBuf },
+    Status {
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Keep polling and print the status whenever it changes"
+        )]
+        watch: bool,
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = Alert::None,
+            help = "Alert when the printer goes offline or runs out of paper (with --watch)"
+        )]
+        alert: Alert,
+        #[arg(
+            long,
+            value_name = "HEX",
+            value_parser = parse_status_hex,
+            conflicts_with = "watch",
+            hide = true,
+            help = "Print the status parsed from the given DLE EOT n=1-4 replies, e.g. \"12 00 00 00\", without connecting to the printer"
+        )]
+        simulate_status: Option<[u8; 4]>,
+        #[arg(
+            long,
+            value_name = "SECONDS",
+            value_parser = clap::value_parser!(u64).range(1..),
+            conflicts_with_all = ["watch", "simulate_status"],
+            help = "Poll the status every SECONDS seconds and update it in place, until interrupted (Ctrl-C)"
+        )]
+        interval: Option<u64>,
+    },
+    Print {
+        file: PathBuf,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Print the estimated paper length and exit without printing"
+        )]
+        estimate: bool,
+        #[arg(
+            long,
+            default_value_t = false,
+            conflicts_with = "estimate",
+            help = "Print the estimated paper length, print time and size of the job (as JSON with --json) and exit without printing"
+        )]
+        report: bool,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Keep CRLF line endings in text files instead of normalizing them to LF"
+        )]
+        keep_cr: bool,
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = LineEnding::Lf,
+            help = "Line ending to send text files with, for printers that only feed on CR. Markdown files are unaffected"
+        )]
+        line_endings: LineEnding,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Reorder right-to-left text (e.g. Arabic, Hebrew) into visual order"
+        )]
+        bidi: bool,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Keep runs of blank lines between markdown blocks instead of collapsing them"
+        )]
+        preserve_lines: bool,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Don't leave blank lines between markdown blocks, to save paper"
+        )]
+        compact: bool,
+        #[arg(
+            long,
+            value_enum,
+            help = "Default alignment of markdown files [default: left], overridden by `<!-- align:... -->` directives. Overrides `align` in markdown front matter"
+        )]
+        align: Option<Align>,
+        #[arg(
+            long = "var",
+            value_name = "KEY=VALUE",
+            value_parser = parse_var,
+            help = "Substitute `{{KEY}}` placeholders in the file with VALUE"
+        )]
+        vars: Vec<(String, String)>,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Fail on placeholders without a value instead of printing them verbatim"
+        )]
+        strict_vars: bool,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Print even if the printer reports that it is out of paper or running low"
+        )]
+        force: bool,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Beep once the job is printed and cut"
+        )]
+        beep_on_done: bool,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Print the CRC-32 of the file's content (after substituting placeholders) as a footer before the cut"
+        )]
+        footer_hash: bool,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Turn characters 90° clockwise, e.g. for spine labels. With the paper turned for them to be upright, lines read bottom to top and follow each other left to right"
+        )]
+        rotate: bool,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Underline URLs (e.g. https://example.com) in the text of markdown files"
+        )]
+        autolink: bool,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Leave out blank lines at the start and end of the file, which would otherwise be fed before the cut. Blank lines within it are kept"
+        )]
+        trim: bool,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Beep three times if the job fails to print"
+        )]
+        beep_on_error: bool,
+        #[arg(
+            long,
+            value_name = "LINES",
+            default_value_t = 0,
+            help = "Feed paper before printing, so that the first line clears the cutter"
+        )]
+        pre_feed: u8,
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "Write the ESC/POS commands to PATH (`-` for stdout) instead of the printer"
+        )]
+        emit: Option<PathBuf>,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Print the parsed markdown tree to stderr, to see which nodes are printed. Nothing is sent to the printer, unless with --emit"
+        )]
+        dump_tree: bool,
+        #[arg(
+            long,
+            value_name = "N",
+            value_parser = clap::value_parser!(u32).range(1..),
+            help = "Check the status after every N top-level blocks of a markdown file, and stop when the paper runs out"
+        )]
+        check_every: Option<u32>,
+        #[arg(
+            long,
+            value_name = "OFFSET",
+            help = "Resume a markdown file stopped by --check-every from the offset it reported, after reloading the paper"
+        )]
+        resume: Option<usize>,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Skip initializing the printer, e.g. to send a .bin/.escpos file byte for byte"
+        )]
+        no_init: bool,
+        #[arg(
+            long,
+            value_enum,
+            help = "Code page the file is encoded in [default: UTF-8]"
+        )]
+        input_encoding: Option<CodePage>,
+        #[arg(
+            long,
+            value_name = "PBM",
+            help = "Print a logo (binary PBM image) centered above the content"
+        )]
+        logo: Option<PathBuf>,
+        #[arg(
+            long,
+            default_value_t = false,
+            requires = "logo",
+            help = "Print the logo as a negative, white on black"
+        )]
+        invert_logo: bool,
+    },
+    /// Keep the printer open and print the files whose paths are read from stdin, one per
+    /// line. The printer is checked between jobs, and reconnected if it went away
+    Serve,
+    /// Probe the printer model and list the features it supports
+    Caps,
+    /// Show the configuration that the global flags resolve to, without connecting to the
+    /// printer
+    Config,
+    /// Show the maker, model and firmware version reported by the printer
+    Info,
+    /// Stop the job being printed and cut, discarding all data queued in the printer
+    Cancel,
+    /// Kick the cash drawer open
+    Drawer {
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = DrawerPin::Pin2,
+            help = "Pin of the drawer kick-out connector that the drawer is connected to"
+        )]
+        pin: DrawerPin,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Kick right away (DLE DC4), even while the printer is busy or stalled, instead of after any queued data (ESC p)"
+        )]
+        realtime: bool,
+    },
+    /// Print a full-width separator line
+    Line {
+        #[arg(value_name = "CHAR", default_value_t = '-')]
+        ch: char,
+    },
+    /// Print a QR code with a caption below it, then cut
+    Qr {
+        data: String,
+        #[arg(long, default_value = "", help = "Text to print below the QR code")]
+        caption: String,
+    },
+    /// Print a ruler with millimeter ticks across the printable width and down the paper, then
+    /// cut, to calibrate margins and placement on pre-printed forms
+    Ruler,
+    /// Print a numeric barcode (e.g. EAN-13) with its digits below it, then cut
+    Barcode {
+        #[arg(
+            help = "Digits to encode. The check digit is computed if left out, e.g. 12 digits for EAN-13"
+        )]
+        data: String,
+        #[arg(long, value_enum, default_value_t = Symbology::Ean13)]
+        symbology: Symbology,
+    },
+    /// Print the characters of a code page as a table, then cut
+    Charset {
+        #[arg(help = "Code page number (ESC t), e.g. 0 for PC437 or 2 for PC850")]
+        page: u8,
+    },
+    /// Print the same QR code at increasing sizes and error correction levels, then cut, to
+    /// find the smallest one that scanners read reliably
+    ScanTest {
+        #[arg(long, default_value = "https://github.com/shenyih0ng/printy")]
+        data: String,
+    },
+}
+
+const SCAN_TEST_ERROR_CORRECTIONS: [QrErrorCorrection; 4] = [
+    QrErrorCorrection::L,
+    QrErrorCorrection::M,
+    QrErrorCorrection::Q,
+    QrErrorCorrection::H,
+];
+// Modules of 6 dots still fit codes of up to 80 modules (version 16) on the 512 dots print width
+const SCAN_TEST_MODULE_SIZES: std::ops::RangeInclusive<u8> = 2..=6;
+
+// How often `serve` checks that the printer is still there while it waits for a job
+const SERVE_POLL_INTERVAL_MS: u64 = 2000;
+
+fn print_job(
+    printer: &mut Printer<Box<dyn Driver>>,
+    content: &str,
+    is_md: bool,
+) -> PrintyResult<()> {
+    if is_md {
+        printer.print_md_and_cut(content)?;
+    } else {
+        printer.print_and_cut(content)?;
+    }
+    Ok(())
+}
+
+/// Lines read from stdin on their own thread, so that they can be waited for with a timeout
+fn stdin_lines() -> Receiver<String> {
+    let (lines_tx, lines_rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if lines_tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    lines_rx
+}
+
+/// Prints the files whose paths are received from `paths`, replying with `ok <PATH>` or
+/// `error <PATH>: <ERROR>` on `out` for each.
+///
+/// While it waits for a job, the printer is polled with a status query, so that a printer that
+/// went away (e.g. unplugged) is reconnected, and thereby initialized again, before the next
+/// job. A job that fails is reported rather than resent, as part of it may have been printed
+/// already; if the printer is gone, it is reconnected before the next job as well.
+fn serve<F>(
+    printer: Printer<Box<dyn Driver>>,
+    connect: F,
+    paths: Receiver<String>,
+    out: &mut impl Write,
+) where
+    F: Fn() -> PrintyResult<Printer<Box<dyn Driver>>>,
+{
+    let reconnect = || match connect() {
+        Ok(printer) => {
+            info!("Reconnected to the printer");
+            Some(printer)
+        }
+        Err(_) => None,
+    };
+
+    let mut printer = Some(printer);
+    loop {
+        let line = match paths.recv_timeout(Duration::from_millis(SERVE_POLL_INTERVAL_MS)) {
+            Ok(line) => line,
+            Err(RecvTimeoutError::Timeout) => {
+                // The debug driver reads its replies from stdin, which the paths come from
+                let is_gone = match printer.as_mut() {
+                    Some(printer) => {
+                        printer.driver.kind().has_printer() && printer.status().is_none()
+                    }
+                    None => true,
+                };
+                if is_gone {
+                    if printer.is_some() {
+                        warn!("Lost the printer, reconnecting");
+                    }
+                    printer = reconnect();
+                }
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        let path = Path::new(line.trim());
+        if path.as_os_str().is_empty() {
+            continue;
+        }
+
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                writeln!(out, "error {}: Failed to read file - {e}", path.display()).ok();
+                continue;
+            }
+        };
+        let is_md = matches!(path.extension(), Some(ext) if ext == "md");
+
+        if printer.is_none() {
+            printer = reconnect();
+        }
+        let Some(active) = printer.as_mut() else {
+            writeln!(out, "error {}: Printer is not connected", path.display()).ok();
+            continue;
+        };
+        match print_job(active, &content, is_md) {
+            Ok(()) => writeln!(out, "ok {}", path.display()).ok(),
+            Err(e) => {
+                if matches!(
+                    e.kind(),
+                    ErrorKind::DeviceNotFound
+                        | ErrorKind::Timeout
+                        | ErrorKind::Io
+                        | ErrorKind::PartialWrite
+                ) {
+                    warn!("Lost the printer ({e}), reconnecting before the next job");
+                    printer = None;
+                }
+                writeln!(out, "error {}: {e}", path.display()).ok()
+            }
+        };
+    }
+}
+
+// Longest job (in millimeters) that is started on a roll whose near-end sensor has tripped.
+// How much paper is left at that point depends on the sensor position, but it is usually at
+// least a meter
+const NEAR_END_MAX_JOB_MM: f32 = 1000.0;
+
+fn compile_md_file(printer: &Printer<Box<dyn Driver>>, path: &Path) -> PrintyResult<Vec<u8>> {
+    let md = std::fs::read_to_string(path).map_err(|e| PrintyError::Parse {
+        context: format!("Failed to read {}", path.display()),
+        source: Some(Box::new(e)),
+    })?;
+    printer.compile_md(&md)
+}
+
+/// Describes `e` for `--json`, with its [`ErrorKind`] for scripts to react to
+fn error_json(e: &PrintyError) -> serde_json::Value {
+    serde_json::json!({ "error": e.to_string(), "kind": e.kind() })
+}
+
+/// Prints the estimated paper length of `compiled`, or with `report` a summary that also
+/// estimates the print time and size of the job
+fn print_estimate(printer: &Printer<Box<dyn Driver>>, compiled: &[u8], report: bool, json: bool) {
+    if !report {
+        println!(
+            "Estimated paper length: {:.1}mm",
+            printer.estimate_length(compiled)
+        );
+        return;
+    }
+
+    let estimate = printer.estimate_job(compiled);
+    if json {
+        println!("{}", serde_json::json!(estimate));
+        return;
+    }
+    println!("Paper length: {:.1}mm", estimate.length_mm);
+    println!(
+        "Print time: {:.1}s (at {:.0}mm/s)",
+        estimate.duration_s, estimate.speed_mm_s
+    );
+    println!("Size: {} bytes", estimate.bytes);
+}
+
+/// Refuses to start printing when the printer is out of paper, or when the job (as compiled by
+/// `compile`) would likely outlast a roll that is running low
+fn check_paper<F>(printer: &mut Printer<Box<dyn Driver>>, compile: F) -> PrintyResult<()>
+where
+    F: FnOnce(&Printer<Box<dyn Driver>>) -> PrintyResult<Vec<u8>>,
+{
+    let Some(status) = printer.status() else {
+        warn!("Unable to determine printer status, printing anyway");
+        return Ok(());
+    };
+
+    match status.paper_status() {
+        PaperStatus::Adequate => {}
+        PaperStatus::NotPresent => {
+            error!("Printer is out of paper, refusing to print (use --force to print anyway)");
+            std::process::exit(1);
+        }
+        PaperStatus::NearEnd => {
+            let length_mm = printer.estimate_length(&compile(printer)?);
+            if length_mm > NEAR_END_MAX_JOB_MM {
+                error!(
+                    "Paper is running low and the job needs about {length_mm:.0}mm, refusing to \
+                     print (use --force to print anyway)"
+                );
+                std::process::exit(1);
+            }
+            warn!("Paper is running low, replace the roll soon");
+        }
+    }
+    Ok(())
+}
+
+/// Strips the whitespace-only lines at the start and end of `text`
+fn trim_blank_lines(text: &str) -> &str {
+    let is_blank = |line: &str| line.trim().is_empty();
+    let start = text
+        .split_inclusive('\n')
+        .take_while(|line| is_blank(line))
+        .map(str::len)
+        .sum();
+    let end = text.len()
+        - text
+            .split_inclusive('\n')
+            .rev()
+            .take_while(|line| is_blank(line))
+            .map(str::len)
+            .sum::<usize>();
+    // Lines are split with their line feeds, so the last line keeps its own
+    &text[start..end.max(start)]
+}
+
+fn open_emit_output(path: &Path) -> PrintyResult<Box<dyn Write>> {
+    if path == Path::new("-") {
+        return Ok(Box::new(io::stdout()));
+    }
+    let file = File::create(path).map_err(|e| PrintyError::Driver {
+        kind: DriverKind::Writer,
+        context: format!("Failed to create {}", path.display()),
+        source: Some(Box::new(e)),
+    })?;
+    Ok(Box::new(file))
+}
+
+/// Connects to the printer (or the debug driver), teeing everything sent to it into the
+/// `--tee` file if given
+fn connect(
+    debug: bool,
+    vid: u16,
+    pid: u16,
+    host: Option<&str>,
+    tee: Option<(&Path, bool)>,
+    config: PrinterConfig,
+) -> PrintyResult<Printer<Box<dyn Driver>>> {
+    let transport = match host {
+        _ if debug => Transport::Debug,
+        // IPv6 addresses are only taken with a port (e.g. `[::1]:9100`), as theirs is ambiguous
+        Some(host) if host.contains(':') => Transport::Network {
+            addr: host.to_string(),
+        },
+        Some(host) => Transport::Network {
+            addr: format!("{host}:{DEFAULT_NETWORK_PORT}"),
+        },
+        None => Transport::Usb { vid, pid },
+    };
+    let builder = Printer::builder(transport).config(config);
+    let Some((path, strict)) = tee else {
+        return builder.build();
+    };
+
+    // Appended to, so that the log outlives reconnects and runs
+    let file = File::options()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| PrintyError::Driver {
+            kind: DriverKind::Writer,
+            context: format!("Failed to open {}", path.display()),
+            source: Some(Box::new(e)),
+        })?;
+    builder.tee(Box::new(file), strict).build()
+}
+
+fn parse_columns(cols: &str) -> Result<Columns, String> {
+    if cols == "auto" {
+        return Ok(Columns::Auto);
+    }
+    match cols.parse::<usize>().map_err(|e| e.to_string())? {
+        0 => Err("Columns must be at least 1".to_string()),
+        cols => Ok(Columns::Fixed(cols)),
+    }
+}
+
+fn parse_chunk_size(size: &str) -> Result<usize, String> {
+    let size = size.parse::<usize>().map_err(|e| e.to_string())?;
+    if !size.is_power_of_two() {
+        return Err("Chunk size must be a power of two".to_string());
+    }
+    Ok(size)
+}
+
+fn parse_init_hex(hex: &str) -> Result<Vec<u8>, String> {
+    match parse_hex(hex) {
+        Ok(bytes) if !bytes.is_empty() => Ok(bytes),
+        Ok(_) => Err("Init sequence is empty".to_string()),
+        Err(e) => Err(format!("Invalid hex format - {e}")),
+    }
+}
+
+fn parse_status_hex(hex: &str) -> Result<[u8; 4], String> {
+    let bytes = parse_hex(hex).map_err(|e| format!("Invalid hex format - {e}"))?;
+    <[u8; 4]>::try_from(bytes)
+        .map_err(|bytes| format!("Expected 4 status bytes, got {}", bytes.len()))
+}
+
+fn parse_cut_hex(hex: &str) -> Result<Vec<u8>, String> {
+    parse_hex(hex).map_err(|e| format!("Invalid hex format - {e}"))
+}
+
+fn parse_feed_mm(mm: &str) -> Result<f32, String> {
+    match mm.parse::<f32>().map_err(|e| e.to_string())? {
+        mm if mm.is_finite() && mm >= 0.0 => Ok(mm),
+        _ => Err("Feed must be a non-negative length".to_string()),
+    }
+}
+
+fn parse_cut_feed_mm(mm: &str) -> Result<f32, String> {
+    match mm.parse::<f32>().map_err(|e| e.to_string())? {
+        mm if mm.is_finite() && (0.0..=MAX_CUT_FEED_MM).contains(&mm) => Ok(mm),
+        _ => Err(format!(
+            "Cut feed must be between 0 and {MAX_CUT_FEED_MM:.1}mm"
+        )),
+    }
+}
+
+fn parse_max_width_mm(mm: &str) -> Result<f32, String> {
+    match mm.parse::<f32>().map_err(|e| e.to_string())? {
+        mm if mm.is_finite() && mm > 0.0 => Ok(mm),
+        _ => Err("Width must be a positive length".to_string()),
+    }
+}
+
+fn parse_var(var: &str) -> Result<(String, String), String> {
+    var.split_once('=')
+        .map(|(key, value)| (key.trim().to_string(), value.to_string()))
+        .ok_or(format!("Invalid variable `{var}`, expected KEY=VALUE"))
+}
+
+/// Resolves the USB IDs and network host (if any) of the printer, explicit `--vid`/`--pid` and
+/// `--host` taking precedence over those of the `--printer` named in the printers file
+fn printer_location(args: &Cli) -> PrintyResult<(u16, u16, Option<String>)> {
+    let named = match &args.printer {
+        Some(name) => {
+            let path = Printers::default_path().ok_or_else(|| PrintyError::Parse {
+                context: "Unable to locate the printers file, as HOME is not set".to_string(),
+                source: None,
+            })?;
+            Some(Printers::load(&path)?.get(name)?)
+        }
+        None => None,
+    };
+    let named = named.as_ref();
+    Ok((
+        args.usb_vendor_id
+            .or(named.and_then(|named| named.vid))
+            .unwrap_or(DEFAULT_VID),
+        args.usb_product_id
+            .or(named.and_then(|named| named.pid))
+            .unwrap_or(DEFAULT_PID),
+        args.host
+            .clone()
+            .or(named.and_then(|named| named.host.clone())),
+    ))
+}
+
+/// Logs to stderr at the level given by `RUST_LOG`, falling back to warnings (or debug
+/// diagnostics with `--verbose`)
+fn init_logging(verbose: bool) {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(if verbose { "debug" } else { "warn" }));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .with_target(false)
+        .without_time()
+        .init();
 }
 
 fn main() -> PrintyResult<()> {
     let args = Cli::parse();
+    init_logging(args.verbose);
+    let (vid, pid, host) = printer_location(&args).unwrap_or_else(|e| {
+        error!("{e}");
+        std::process::exit(1);
+    });
+
+    if let Some(size) = args.chunk_size
+        && size > MAX_CHUNK_SIZE
+    {
+        warn!("Chunk size {size} exceeds the maximum, using {MAX_CHUNK_SIZE} instead");
+    }
+
+    let retry = RetryPolicy {
+        max_attempts: args.max_retries.saturating_add(1),
+        backoff: Duration::from_millis(args.retry_backoff_ms),
+    };
+    let mut config = PrinterConfig {
+        usb: UsbOptions {
+            chunk_size: args.chunk_size.map(|size| size.min(MAX_CHUNK_SIZE)),
+            retry: retry.clone(),
+            wait_for_device: Duration::from_millis(args.wait_for_device),
+            bus: args.bus,
+            address: args.address,
+        },
+        network: NetworkOptions {
+            connect_timeout: Duration::from_millis(args.connect_timeout_ms),
+            io_timeout: Duration::from_millis(args.timeout_ms),
+            retry,
+        },
+        columns: match args.cols {
+            Some(Columns::Fixed(cols)) => cols,
+            _ => DEFAULT_COLUMNS,
+        },
+        font_b_columns: args
+            .font_b_cols
+            .map_or(DEFAULT_FONT_B_COLUMNS, |cols| cols as usize),
+        drain_on_init: !args.no_drain,
+        disable_asb: !args.keep_asb,
+        status_method: args.status_method,
+        ignore_near_end: args.ignore_near_end,
+        feed_before_cut_mm: args.feed_mm,
+        cut_feed_mm: args.cut_feed,
+        max_width_mm: args.max_width_mm,
+        encoding: args.encoding,
+        glyph_fallback: args.glyph_fallback,
+        qr_model: args.qr_model,
+        strong_style: args.strong_style,
+        blockquote_style: args.blockquote_style,
+        shadow_headings: args.shadow_headings,
+        image_density: args.image_density,
+        hex_width: args.hex_width,
+        ..PrinterConfig::default()
+    };
+    if let Some(init_cmd) = args.init_hex {
+        config.init_cmd = init_cmd;
+    }
+    if let Some(path) = &args.glyph_font {
+        config.glyph_font = Some(Arc::new(GlyphFont::from_file(path)?));
+    }
+    if let Some(cut_cmd) = args.cut_hex {
+        if cut_cmd.is_empty() {
+            warn!("Cut sequence is empty, paper will not be cut");
+        }
+        config.cut_cmd = cut_cmd;
+    }
+    if args.no_cut {
+        config.cut_cmd = Vec::new();
+    }
 
-    let mut printer = match if args.debug {
-        Printer::debug()
+    if let Commands::Config = args.command {
+        print!("{config}");
+        if let Some(Columns::Auto) = args.cols {
+            println!("(Columns are derived from the printer model once connected)");
+        }
+        if let ItalicStyleArg::Auto = args.italic_style {
+            println!("(Emphasis style is derived from the printer model once connected)");
+        }
+        return Ok(());
+    }
+
+    // Parsed as if read from the printer, to exercise the handling of each state without one
+    if let Commands::Status {
+        simulate_status: Some(bytes),
+        ..
+    } = &args.command
+    {
+        match PrinterStatus::from_bytes(bytes) {
+            Some(status) if config.ignore_near_end => println!("{}", status.ignoring_near_end()),
+            Some(status) => println!("{status}"),
+            None => println!("Unable to determine printer status!"),
+        }
+        return Ok(());
+    }
+
+    let emit_path = match &args.command {
+        Commands::Print { emit, no_init, .. } => {
+            config.skip_init = *no_init;
+            emit.clone()
+        }
+        // Anything sent before the buffer is cleared would be discarded with it
+        Commands::Cancel => {
+            config.skip_init = true;
+            None
+        }
+        _ => None,
+    };
+
+    // The tree is dumped without a printer, the commands sent to it are discarded
+    let dump_tree_only = matches!(
+        args.command,
+        Commands::Print {
+            dump_tree: true,
+            emit: None,
+            ..
+        }
+    );
+
+    let tee = args.tee.as_deref().map(|path| (path, args.tee_strict));
+    let mut printer = match if dump_tree_only {
+        Printer::writer(Box::new(io::sink()), config)
+    } else if let Some(path) = &emit_path {
+        open_emit_output(path).and_then(|output| Printer::writer(output, config))
     } else {
-        Printer::usb(args.usb_vendor_id, args.usb_product_id)
+        connect(args.debug, vid, pid, host.as_deref(), tee, config)
     } {
         Ok(printer) => printer,
+        Err(e) if args.json => {
+            eprintln!("{}", error_json(&e));
+            std::process::exit(1);
+        }
         Err(e) => {
-            println!(
+            // Stdout may already be closed, if that is what failed the debug driver
+            writeln!(
+                io::stdout(),
                 r"
     .--.
    |    |
@@ -63,33 +1137,647 @@ fn main() -> PrintyResult<()> {
 |  (T ᴖ T) |
 |__________|
 '----------'"
-            );
-            eprintln!("{e}");
+            )
+            .ok();
+            error!("{e}");
             std::process::exit(1);
         }
     };
 
+    // Only a real printer is probed, the emitted file or the tree keep the default widths
+    if let Some(Columns::Auto) = args.cols {
+        match printer.detect_columns() {
+            Some(cols) => printer.config.columns = cols,
+            None if printer.driver.kind().has_printer() => warn!(
+                "Unable to identify the printer model, using {DEFAULT_COLUMNS} columns per line"
+            ),
+            None => {}
+        }
+        if args.font_b_cols.is_none()
+            && let Some(cols) = printer.detect_font_b_columns()
+        {
+            printer.config.font_b_columns = cols;
+        }
+    }
+
+    printer.config.italic_style = match args.italic_style {
+        ItalicStyleArg::Italic => ItalicStyle::Italic,
+        ItalicStyleArg::Underline => ItalicStyle::Underline,
+        ItalicStyleArg::Auto => match printer.capabilities() {
+            Some(caps) if caps.supports(Feature::Italic) == Some(true) => ItalicStyle::Italic,
+            _ => ItalicStyle::Underline,
+        },
+    };
+    // Unknown models are given the benefit of the doubt, as with `Printer::print_shadow`
+    if printer.config.shadow_headings
+        && let Some(caps) = printer.capabilities()
+        && caps.supports(Feature::ReverseFeed) == Some(false)
+    {
+        warn!(
+            "{} is not supported by {}, headings will not be shadowed",
+            Feature::ReverseFeed,
+            caps.model_name()
+        );
+        printer.config.shadow_headings = false;
+    }
+
+    // Compiled once, with the settings of the printer, and printed with every job
+    if let Some(path) = &args.header {
+        printer.config.job_header = compile_md_file(&printer, path)?;
+    }
+    if let Some(path) = &args.footer {
+        printer.config.job_footer = compile_md_file(&printer, path)?;
+    }
+
+    if let Some(level) = args.density {
+        printer.set_density(level)?;
+    }
+
     match args.command {
-        Commands::Print { file } => {
-            let content = std::fs::read_to_string(&file).unwrap_or_else(|_| {
-                eprintln!("Failed to read file: {}", file.display());
+        Commands::Print {
+            file,
+            estimate,
+            report,
+            keep_cr,
+            line_endings,
+            bidi,
+            preserve_lines,
+            compact,
+            align,
+            vars,
+            strict_vars,
+            force,
+            beep_on_done,
+            footer_hash,
+            rotate,
+            autolink,
+            trim,
+            beep_on_error,
+            pre_feed,
+            emit,
+            dump_tree,
+            check_every,
+            resume,
+            no_init,
+            input_encoding,
+            logo,
+            invert_logo,
+        } => {
+            printer.config.keep_cr = keep_cr;
+            printer.config.line_ending = line_endings;
+            printer.config.bidi = bidi;
+            printer.config.preserve_lines = preserve_lines;
+            printer.config.compact = compact;
+            printer.config.footer_hash = footer_hash;
+            printer.config.autolink = autolink;
+            // Images are linked relative to the markdown file
+            printer.config.image_base_dir = file.parent().map(Path::to_path_buf);
+            // The report estimates the print time with the speed of the model
+            if report {
+                printer.capabilities();
+            }
+
+            let content = std::fs::read(&file).unwrap_or_else(|e| {
+                error!("Failed to read file: {} - {e}", file.display());
                 std::process::exit(1);
             });
 
-            match file.extension() {
-                Some(ext) if ext == "md" => {
-                    printer.print_md(&content)?.cut()?;
+            // Pre-generated ESC/POS streams are sent as-is, without templating or a cut
+            if matches!(file.extension(), Some(ext) if ext == "bin" || ext == "escpos") {
+                if estimate || report {
+                    print_estimate(&printer, &content, report, args.json);
+                    return Ok(());
+                }
+                // Querying the status would add bytes to a verbatim stream
+                if !args.debug && emit.is_none() && !force && !no_init {
+                    check_paper(&mut printer, |_| Ok(content.clone()))?;
+                }
+                printer.print_raw(&content)?.flush(false)?;
+                return Ok(());
+            }
+
+            let content = match input_encoding {
+                Some(page) => page.decode(&content),
+                None => String::from_utf8(content).unwrap_or_else(|e| {
+                    error!(
+                        "{} is not valid UTF-8 (at byte {}), use --input-encoding to read it \
+                         from a code page",
+                        file.display(),
+                        e.utf8_error().valid_up_to()
+                    );
+                    std::process::exit(1);
+                }),
+            };
+            let content = template::render(&content, &HashMap::from_iter(vars), strict_vars)?;
+            let content = if trim {
+                trim_blank_lines(&content).to_string()
+            } else {
+                content
+            };
+            let is_md = matches!(file.extension(), Some(ext) if ext == "md");
+
+            // Flags take precedence over the front matter, which takes precedence over the
+            // built-in defaults
+            if is_md {
+                let cli_columns = args.cols.is_some().then_some(printer.config.columns);
+                if let (Some(block), _) = front_matter::split(&content) {
+                    FrontMatter::parse(block)?.apply(&mut printer.config);
+                }
+                printer.config.apply_front_matter = false;
+                if let Some(cols) = cli_columns {
+                    printer.config.columns = cols;
+                }
+            }
+            if let Some(align) = align {
+                printer.config.align = align;
+            }
+
+            let checked = check_every.is_some() || resume.is_some();
+            if checked && !is_md {
+                warn!(
+                    "--check-every and --resume only apply to markdown files, printing {} at once",
+                    file.display()
+                );
+            }
+
+            if dump_tree {
+                if is_md {
+                    let (_, md) = front_matter::split(&content);
+                    eprintln!("{:#?}", parse_md(md)?);
+                } else {
+                    warn!(
+                        "{} is not a markdown file, there is no tree to dump",
+                        file.display()
+                    );
+                }
+                if emit.is_none() {
+                    return Ok(());
+                }
+            }
+
+            if args.encoding == Encoding::Auto
+                && let Some(page) = args.encoding.code_page(&content)
+            {
+                info!("Using code page {page}");
+            }
+
+            if content.trim().is_empty() {
+                // Skip the cut as well, which would otherwise waste a blank slip
+                warn!("Nothing to print: {} is empty", file.display());
+                return Ok(());
+            }
+
+            if estimate || report {
+                let compiled = if is_md {
+                    printer.compile_md(&content)?
+                } else {
+                    content.into_bytes()
+                };
+                print_estimate(&printer, &compiled, report, args.json);
+                return Ok(());
+            }
+
+            // Neither the debug driver nor emitted output has paper to check
+            if !args.debug && emit.is_none() && !force {
+                check_paper(&mut printer, |printer| {
+                    if is_md {
+                        printer.compile_md(&content)
+                    } else {
+                        Ok(content.as_bytes().to_vec())
+                    }
+                })?;
+            }
+
+            if pre_feed > 0 {
+                printer.feed(pre_feed)?;
+            }
+
+            if let Some(logo) = logo {
+                let pbm = std::fs::read(&logo).unwrap_or_else(|e| {
+                    error!("Failed to read logo: {} - {e}", logo.display());
+                    std::process::exit(1);
+                });
+                let raster = Raster::from_pbm(&pbm)?;
+                let raster = if invert_logo { raster.invert() } else { raster };
+                printer
+                    .align(Align::Center)?
+                    .print_stored_graphics(LOGO_GRAPHICS_KEY, &raster)?
+                    .align(Align::Left)?;
+            }
+
+            if rotate {
+                printer.rotate_90(true)?;
+            }
+            // Ctrl-C stops the job and cuts the paper, rather than leaving a half-printed job
+            // queued in the printer. Only a printer has a job to stop, unlike the debug driver
+            // or emitted output.
+            if !args.debug && emit.is_none() {
+                let interrupt = Arc::new(AtomicBool::new(false));
+                let handler_interrupt = interrupt.clone();
+                match ctrlc::set_handler(move || {
+                    // A second Ctrl-C exits right away, e.g. while a write to an unresponsive
+                    // printer blocks
+                    if handler_interrupt.swap(true, Ordering::Relaxed) {
+                        std::process::exit(INTERRUPTED_EXIT_CODE);
+                    }
+                }) {
+                    Ok(()) => printer.config.interrupt = Some(interrupt),
+                    Err(e) => warn!("Failed to handle Ctrl-C, interrupting will not cut - {e}"),
                 }
-                _ => {
-                    printer.print(&content)?.cut()?;
+            }
+
+            // `None` once the whole job is printed, or where it stopped
+            let printed = if is_md && checked {
+                printer.print_md_checked_and_cut(
+                    &content,
+                    check_every.map_or(usize::MAX, |n| n as usize),
+                    resume.unwrap_or(0),
+                )
+            } else if is_md {
+                printer.print_md_and_cut(&content).map(|_| None)
+            } else {
+                printer.print_and_cut(&content).map(|_| None)
+            };
+            // After a failure, the next init resets the rotation anyway
+            if rotate && printed.is_ok() {
+                printer.rotate_90(false)?;
+            }
+            match printed {
+                Ok(None) if beep_on_done => {
+                    printer.beep(JOB_DONE_BEEPS)?;
+                }
+                Ok(None) => {}
+                Ok(Some(offset)) => {
+                    if beep_on_error {
+                        let _ = printer.beep(JOB_ERROR_BEEPS);
+                    }
+                    printer.flush(false)?;
+                    error!(
+                        "Printer is not ready (e.g. out of paper), stopped at offset {offset}. \
+                         Reload the paper, then resume with --resume {offset}"
+                    );
+                    std::process::exit(1);
+                }
+                Err(PrintyError::Interrupted { .. }) => {
+                    printer.abort_job()?.flush(false)?;
+                    error!("Interrupted, the rest of the job was discarded and the paper cut");
+                    std::process::exit(INTERRUPTED_EXIT_CODE);
+                }
+                Err(e) => {
+                    if beep_on_error {
+                        // The printer may well be unable to beep either, which changes nothing
+                        let _ = printer.beep(JOB_ERROR_BEEPS);
+                    }
+                    return Err(e);
                 }
             }
+            printer.flush(false)?;
+        }
+        // Handled before connecting to the printer
+        Commands::Config => unreachable!(),
+        Commands::Caps => {
+            // Asked for explicitly, so the debug driver is probed too (with typed in replies)
+            let caps = if printer.driver.kind().has_printer() {
+                printer.capabilities().cloned()
+            } else {
+                printer.probe_capabilities()
+            };
+            match caps {
+                Some(caps) => print!("{caps}"),
+                None => println!("Unable to identify printer model!"),
+            }
+        }
+        Commands::Serve => {
+            let config = printer.config.clone();
+            serve(
+                printer,
+                || connect(args.debug, vid, pid, host.as_deref(), tee, config.clone()),
+                stdin_lines(),
+                &mut io::stdout(),
+            );
+            return Ok(());
+        }
+        Commands::Info => {
+            let unknown = || "unknown".to_string();
+            println!("Maker: {}", printer.maker().unwrap_or_else(unknown));
+            println!("Model: {}", printer.model_name().unwrap_or_else(unknown));
+            println!(
+                "Firmware: {}",
+                printer.firmware_version().unwrap_or_else(unknown)
+            );
+        }
+        Commands::Cancel => {
+            warn!("Discarding all data queued in the printer, including any unprinted jobs");
+            if !printer.clear_buffer_realtime()? {
+                warn!("Printer did not acknowledge clearing its buffer");
+            }
+            printer.cut()?;
+        }
+        Commands::Drawer { pin, realtime } => {
+            if realtime {
+                printer.open_drawer_realtime(pin)?;
+            } else {
+                printer.open_drawer(pin)?;
+            }
+        }
+        Commands::Line { ch } => {
+            printer.separator(ch)?;
+        }
+        Commands::Qr { data, caption } => {
+            printer.qr_with_caption(&data, &caption)?.cut()?;
+        }
+        Commands::Ruler => {
+            printer.ruler()?.cut()?;
+        }
+        Commands::Barcode { data, symbology } => {
+            printer
+                .align(Align::Center)?
+                .barcode(symbology, &data)?
+                .align(Align::Left)?
+                .cut()?;
+        }
+        Commands::Charset { page } => {
+            printer.codepage_table(page)?.cut()?;
+        }
+        Commands::ScanTest { data } => {
+            printer.align(Align::Center)?;
+            for error_correction in SCAN_TEST_ERROR_CORRECTIONS {
+                for module_size in SCAN_TEST_MODULE_SIZES {
+                    printer
+                        .qr(&data, module_size, error_correction)?
+                        .print(&format!("\nSize {module_size}, EC {error_correction}\n\n"))?;
+                }
+            }
+            printer.align(Align::Left)?.cut()?;
+        }
+        Commands::Status {
+            watch: true, alert, ..
+        } => {
+            let mut last_status_text = None;
+            // Assume ready initially, so that starting in an error state alerts too
+            let mut was_ready = true;
+            loop {
+                let status = printer.status();
+                let status_text = match &status {
+                    Some(status) => status.to_string(),
+                    None => "Unable to determine printer status!".to_string(),
+                };
+                if last_status_text.as_ref() != Some(&status_text) {
+                    println!("{status_text}");
+                }
+
+                // Alert once per transition into an error state, rather than on every poll
+                let is_ready = status.is_some_and(|status| status.is_ready());
+                if is_ready != was_ready {
+                    info!(
+                        "Printer is {}",
+                        if is_ready { "ready" } else { "not ready" }
+                    );
+                }
+                if was_ready && !is_ready {
+                    match alert {
+                        Alert::Beep => {
+                            if let Err(e) = printer.beep(WATCH_ALERT_BEEPS) {
+                                warn!("Failed to sound the alert - {e}");
+                            }
+                        }
+                        Alert::Bell => {
+                            print!("\x07");
+                            io::stdout().flush().ok();
+                        }
+                        Alert::None => {}
+                    }
+                }
+
+                last_status_text = Some(status_text);
+                was_ready = is_ready;
+                sleep(Duration::from_millis(WATCH_INTERVAL_MS));
+            }
+        }
+        Commands::Status {
+            interval: Some(interval),
+            ..
+        } => {
+            // Only a terminal can have the previous status erased (by moving the cursor up)
+            let in_place = !args.json && io::stdout().is_terminal();
+            let mut last_line_count = 0;
+            loop {
+                let status = printer.status();
+                let status_text = if args.json {
+                    match &status {
+                        Some(status) => serde_json::json!({
+                            "ready": status.is_ready(),
+                            "status": status,
+                        }),
+                        None => serde_json::json!({
+                            "ready": false,
+                            "error": "Unable to determine printer status",
+                        }),
+                    }
+                    .to_string()
+                } else {
+                    match &status {
+                        Some(status) => status.to_string(),
+                        None => "Unable to determine printer status!".to_string(),
+                    }
+                };
+
+                if in_place && last_line_count > 0 {
+                    // Move to the start of the first line of the previous status, and clear
+                    // everything below it
+                    print!("\x1b[{last_line_count}F\x1b[J");
+                }
+                println!("{}", status_text.trim_end());
+                io::stdout().flush().ok();
+                last_line_count = status_text.trim_end().lines().count();
+
+                sleep(Duration::from_secs(interval));
+            }
+        }
+        Commands::Status { .. } => {
+            match printer.status() {
+                Some(status) => println!("{status}"),
+                None => println!("Unable to determine printer status!"),
+            }
+            if let StatusMethod::GsR = args.status_method
+                && let Some(is_pin_high) = printer.drawer_status()
+            {
+                println!(
+                    "Drawer connector (pin 3): {}",
+                    if is_pin_high { "HIGH" } else { "LOW" }
+                );
+            }
         }
-        Commands::Status => match printer.status() {
-            Some(status) => println!("{status}"),
-            None => println!("Unable to determine printer status!"),
-        },
+    }
+
+    if args.debug {
+        println!("Total bytes written: {}", printer.bytes_written());
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn trim_blank_lines_strips_surrounding_blank_lines() {
+        assert_eq!(
+            trim_blank_lines("\n \n\tTotal 3.50\n\n \n"),
+            "\tTotal 3.50\n"
+        );
+        assert_eq!(trim_blank_lines("Total 3.50\n  "), "Total 3.50\n");
+        assert_eq!(trim_blank_lines("Total 3.50"), "Total 3.50");
+    }
+
+    #[test]
+    fn trim_blank_lines_keeps_inner_blank_lines() {
+        assert_eq!(trim_blank_lines("\nTea\n\n\nCake\n\n"), "Tea\n\n\nCake\n");
+    }
+
+    #[test]
+    fn trim_blank_lines_leaves_no_trailing_feeds() {
+        let trimmed = trim_blank_lines("Tea\n\n\n\n");
+        assert!(!trimmed.ends_with("\n\n"));
+        assert_eq!(trim_blank_lines(" \n\n "), "");
+    }
+
+    // Driver that writes into `written`, or fails every write (counted in `failed_writes`) as
+    // an unplugged printer does
+    #[derive(Clone, Default)]
+    struct MockDriver {
+        written: Rc<RefCell<Vec<u8>>>,
+        failed_writes: Rc<Cell<usize>>,
+        unplugged: bool,
+    }
+
+    impl Driver for MockDriver {
+        fn kind(&self) -> DriverKind {
+            DriverKind::Debug
+        }
+
+        fn read(&mut self, _buf: &mut [u8]) -> PrintyResult<usize> {
+            Ok(0)
+        }
+
+        fn write(&mut self, data: &[u8]) -> PrintyResult<usize> {
+            if self.unplugged {
+                self.failed_writes.set(self.failed_writes.get() + 1);
+                return Err(PrintyError::Driver {
+                    kind: DriverKind::Usb,
+                    context: "Write failed".to_string(),
+                    source: Some(Box::new(io::Error::from(io::ErrorKind::BrokenPipe))),
+                });
+            }
+            self.written.borrow_mut().extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn drain(&mut self) -> PrintyResult<()> {
+            Ok(())
+        }
+    }
+
+    fn mock_printer(driver: &MockDriver, skip_init: bool) -> Printer<Box<dyn Driver>> {
+        let config = PrinterConfig {
+            skip_init,
+            ..PrinterConfig::default()
+        };
+        Printer::new(Box::new(driver.clone()), config).unwrap()
+    }
+
+    // Serves `jobs` (file name and content, written to a temporary directory) to completion,
+    // returning the replies
+    fn serve_jobs<F>(printer: Printer<Box<dyn Driver>>, connect: F, jobs: &[(&str, &str)]) -> String
+    where
+        F: Fn() -> PrintyResult<Printer<Box<dyn Driver>>>,
+    {
+        let dir = std::env::temp_dir().join(format!("printy-serve-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (paths_tx, paths_rx) = mpsc::channel();
+        for (name, content) in jobs {
+            let path = dir.join(name);
+            std::fs::write(&path, content).unwrap();
+            paths_tx.send(path.display().to_string()).unwrap();
+        }
+        drop(paths_tx);
+
+        let mut out = Vec::new();
+        serve(printer, connect, paths_rx, &mut out);
+        std::fs::remove_dir_all(&dir).unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .replace(&format!("{}/", dir.display()), "")
+    }
+
+    #[test]
+    fn serve_reports_a_failed_job_and_reconnects_before_the_next() {
+        let unplugged = MockDriver {
+            unplugged: true,
+            ..MockDriver::default()
+        };
+        let replugged = MockDriver::default();
+        let connects = Cell::new(0);
+        let out = serve_jobs(
+            mock_printer(&unplugged, true),
+            || {
+                connects.set(connects.get() + 1);
+                Ok(mock_printer(&replugged, false))
+            },
+            &[("first.txt", "Tea"), ("second.txt", "Cake")],
+        );
+
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("error first.txt: "));
+        assert_eq!(lines[1], "ok second.txt");
+        // The failed job was not resent, and the next one went to the re-initialized printer
+        assert_eq!(unplugged.failed_writes.get(), 1);
+        assert_eq!(connects.get(), 1);
+        let written = replugged.written.borrow();
+        assert!(written.starts_with(&[0x1B, b'@']));
+        assert!(!written.windows(3).any(|w| w == b"Tea"));
+        assert!(written.windows(4).any(|w| w == b"Cake"));
+    }
+
+    #[test]
+    fn serve_reports_jobs_while_the_printer_is_gone() {
+        let unplugged = MockDriver {
+            unplugged: true,
+            ..MockDriver::default()
+        };
+        let out = serve_jobs(
+            mock_printer(&unplugged, true),
+            || {
+                Err(PrintyError::Driver {
+                    kind: DriverKind::Usb,
+                    context: "Printer not found".to_string(),
+                    source: None,
+                })
+            },
+            &[("first.txt", "Tea"), ("second.txt", "Cake")],
+        );
+
+        let lines = out.lines().collect::<Vec<_>>();
+        assert!(lines[0].starts_with("error first.txt: "));
+        assert_eq!(lines[1], "error second.txt: Printer is not connected");
+        assert_eq!(unplugged.failed_writes.get(), 1);
+    }
+
+    #[test]
+    fn json_errors_report_their_kind() {
+        let e = PrintyError::Driver {
+            kind: DriverKind::Usb,
+            context: "Failed to open device".to_string(),
+            source: Some(Box::new(rusb::Error::Access)),
+        };
+        assert_eq!(
+            error_json(&e),
+            serde_json::json!({ "error": e.to_string(), "kind": "access_denied" })
+        );
+    }
+}
@@ -1,23 +1,72 @@
 use hexyl;
 use rusb::{Context, DeviceHandle, Direction, TransferType, UsbContext};
+use serde::Serialize;
 use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
     fmt,
-    io::{self},
+    io::{self, IsTerminal, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    num::ParseIntError,
+    ops::Range,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
     thread::sleep,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+use crate::codepage::{CodePage, Encoding, GlyphFallback};
 use crate::escpos::{
-    CMD_BOLD, CMD_CHAR_SIZE, CMD_CUT, CMD_DISABLE_ASB, CMD_INIT, CMD_PROC_DELAY_MS, CMD_RT_STATUS,
-    CMD_UNDERLINE, PrinterStatus, RtStatusReq,
+    CMD_ABS_POSITION, CMD_BARCODE, CMD_BARCODE_HRI_BELOW, CMD_BEEP, CMD_BOLD, CMD_CHAR_SIZE,
+    CMD_CODEPAGE, CMD_CUT, CMD_CUT_FEED, CMD_DEFINE_GRAPHICS, CMD_DISABLE_ASB, CMD_FEED_LINES,
+    CMD_FEED_MM, CMD_FONT_B, CMD_IDENTITY, CMD_INIT, CMD_ITALIC, CMD_JUSTIFY, CMD_LEFT_MARGIN,
+    CMD_PRINT_DENSITY, CMD_PRINT_GRAPHICS, CMD_PROC_DELAY_MS, CMD_PULSE, CMD_QR_ERROR_CORRECTION,
+    CMD_QR_MODEL, CMD_QR_MODULE_SIZE, CMD_QR_PRINT, CMD_QR_STORE, CMD_REVERSE,
+    CMD_REVERSE_FEED_UNITS, CMD_ROTATE_90, CMD_RT_CLEAR_BUFFER, CMD_RT_GENERATE_PULSE,
+    CMD_RT_STATUS, CMD_TRANSMIT_STATUS, CMD_UNDERLINE, CMD_USER_SETTING_ENTER,
+    CMD_USER_SETTING_EXIT, Capabilities, DEFAULT_COLUMNS, DEFAULT_FONT_B_COLUMNS,
+    DEFAULT_LINE_HEIGHT_MM, DEFAULT_PRINT_SPEED_MM_S, DEFAULT_PRINT_WIDTH_MM, DOTS_PER_MM,
+    DrawerPin, FONT_A_WIDTH_DOTS, FONT_B_WIDTH_DOTS, Feature, GRAPHICS_KEY_RANGE,
+    GRAPHICS_MAX_DATA_LEN, IDENTITY_STRING_MAX_LEN, IdentityReq, ImageDensity, JustifyReq,
+    PaperStatus, PrinterStatus, QR_CAPTION_MODULE_SIZE, QrErrorCorrection, QrModel,
+    RT_CLEAR_BUFFER_REPLY, RT_STATUS_BUF_LEN, RT_STATUS_REPLY_DELAY_MS, RT_STATUS_REQS, Symbology,
+    TransmitStatusReq, VERTICAL_MOTION_UNITS_PER_INCH, check_digit, cut_feed_units,
+    drawer_pin_high_from_transmit_byte, estimate_length_mm, parse_identity_string, rt_status_byte,
+    transmit_status_byte,
 };
+use crate::front_matter::{self, FrontMatter};
+use crate::glyph::{self, GlyphFont};
+use crate::raster::Raster;
 
 use markdown::{mdast, to_mdast};
+use tracing::{debug, warn};
+use unicode_bidi::{BidiClass, BidiInfo};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum DriverKind {
     Debug,
     Usb,
+    Network,
+    Writer,
+}
+
+/// Coarse classification of a [`PrintyError`], for callers that need to react to specific
+/// failures (e.g. retrying on timeouts) without matching on error messages. Serialized in
+/// `snake_case` (e.g. `device_not_found`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    DeviceNotFound,
+    AccessDenied,
+    Timeout,
+    PartialWrite,
+    Parse,
+    Unsupported,
+    Interrupted,
+    Io,
 }
 
 #[derive(Debug)]
@@ -25,12 +74,88 @@ pub enum PrintyError {
     Driver {
         kind: DriverKind,
         context: String,
-        source: Option<Box<dyn std::error::Error>>,
+        source: Option<Box<dyn std::error::Error + Send + Sync>>,
     },
     Parse {
         context: String,
-        source: Option<Box<dyn std::error::Error>>,
+        source: Option<Box<dyn std::error::Error + Send + Sync>>,
+    },
+    Unsupported {
+        context: String,
+    },
+    PartialWrite {
+        kind: DriverKind,
+        expected: usize,
+        written: usize,
     },
+    /// A write was stopped part-way because [`PrinterConfig::interrupt`] was set
+    Interrupted {
+        written: usize,
+    },
+}
+
+impl DriverKind {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            DriverKind::Debug => "Debug",
+            DriverKind::Usb => "USB",
+            DriverKind::Network => "Network",
+            DriverKind::Writer => "Writer",
+        }
+    }
+
+    /// Whether the driver talks to a printer that replies to queries. The debug driver only
+    /// replies with what is typed in, and the writer driver never does, so queries (e.g. to
+    /// probe the model) are not sent through them unless requested explicitly.
+    pub fn has_printer(&self) -> bool {
+        matches!(self, DriverKind::Usb | DriverKind::Network)
+    }
+}
+
+impl PrintyError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            PrintyError::Driver { source, .. } => {
+                if let Some(e) = source
+                    .as_deref()
+                    .and_then(|e| e.downcast_ref::<io::Error>())
+                {
+                    return match e.kind() {
+                        io::ErrorKind::NotFound
+                        | io::ErrorKind::ConnectionRefused
+                        | io::ErrorKind::HostUnreachable => ErrorKind::DeviceNotFound,
+                        io::ErrorKind::PermissionDenied => ErrorKind::AccessDenied,
+                        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ErrorKind::Timeout,
+                        _ => ErrorKind::Io,
+                    };
+                }
+                match source
+                    .as_deref()
+                    .and_then(|e| e.downcast_ref::<rusb::Error>())
+                {
+                    Some(rusb::Error::NotFound | rusb::Error::NoDevice) => {
+                        ErrorKind::DeviceNotFound
+                    }
+                    Some(rusb::Error::Access) => ErrorKind::AccessDenied,
+                    Some(rusb::Error::Timeout) => ErrorKind::Timeout,
+                    _ => ErrorKind::Io,
+                }
+            }
+            PrintyError::Parse { .. } => ErrorKind::Parse,
+            PrintyError::Unsupported { .. } => ErrorKind::Unsupported,
+            PrintyError::PartialWrite { .. } => ErrorKind::PartialWrite,
+            PrintyError::Interrupted { .. } => ErrorKind::Interrupted,
+        }
+    }
+
+    /// Number of bytes written before a [`PrintyError::PartialWrite`], i.e. the offset to
+    /// resume from with [`Printer::print_from_offset`]
+    pub fn written(&self) -> Option<usize> {
+        match self {
+            PrintyError::PartialWrite { written, .. } => Some(*written),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for PrintyError {
@@ -41,15 +166,7 @@ impl fmt::Display for PrintyError {
                 context,
                 source,
             } => {
-                write!(
-                    f,
-                    "Driver ({}) error: {}",
-                    match kind {
-                        DriverKind::Debug => "Debug",
-                        DriverKind::Usb => "USB",
-                    },
-                    context
-                )?;
+                write!(f, "Driver ({}) error: {}", kind.name(), context)?;
 
                 if let Some(source_err) = source {
                     write!(f, " - {}", source_err)?;
@@ -64,48 +181,123 @@ impl fmt::Display for PrintyError {
                 }
                 Ok(())
             }
+            PrintyError::Unsupported { context } => write!(f, "Unsupported: {}", context),
+            PrintyError::PartialWrite {
+                kind,
+                expected,
+                written,
+            } => write!(
+                f,
+                "Driver ({}) error: Partial write: expected {}, got {}",
+                kind.name(),
+                expected,
+                written
+            ),
+            PrintyError::Interrupted { written } => {
+                write!(f, "Interrupted after writing {} bytes", written)
+            }
         }
     }
 }
 
-impl std::error::Error for PrintyError {}
+impl std::error::Error for PrintyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PrintyError::Driver { source, .. } | PrintyError::Parse { source, .. } => {
+                source.as_deref().map(|e| e as _)
+            }
+            PrintyError::Unsupported { .. }
+            | PrintyError::PartialWrite { .. }
+            | PrintyError::Interrupted { .. } => None,
+        }
+    }
+}
 
 pub type PrintyResult<T> = Result<T, PrintyError>;
 
 pub trait Driver {
+    /// Transport of the driver, for diagnostics
+    fn kind(&self) -> DriverKind;
+
     fn read(&mut self, buf: &mut [u8]) -> PrintyResult<usize>;
 
     fn write(&mut self, data: &[u8]) -> PrintyResult<usize>;
 
     fn drain(&mut self) -> PrintyResult<()>;
+
+    /// Sends any bytes buffered by the driver to the device. Drivers that write through (as
+    /// all current drivers do) have nothing to flush.
+    fn flush(&mut self) -> PrintyResult<()> {
+        Ok(())
+    }
 }
 
-#[derive(Default)]
+/// Parses whitespace separated hex bytes, e.g. `"0x1b 0x40"` or `"1b 40"`
+pub fn parse_hex(input: &str) -> Result<Vec<u8>, ParseIntError> {
+    input
+        .split_whitespace()
+        .map(|s| {
+            if s.starts_with("0x") || s.starts_with("0X") {
+                u8::from_str_radix(&s[2..], 16)
+            } else {
+                u8::from_str_radix(s, 16)
+            }
+        })
+        .collect()
+}
+
+// Width of the hex dump when it is not written to a terminal, which fits 2 panels
+const DEFAULT_HEX_WIDTH: usize = 80;
+
+// Columns taken by the offset panel and borders of the hex dump, and by each panel of 8 bytes
+const HEX_BASE_WIDTH: usize = 10;
+const HEX_PANEL_WIDTH: usize = 35;
+
 pub struct DebugDriver {
     write_count: usize,
     read_count: usize,
+    num_panels: u64,
+}
+
+impl DebugDriver {
+    /// Dumps written bytes in as many panels as fit `hex_width` columns, or the terminal's
+    /// width (from `COLUMNS`) if it is not given
+    pub fn new(hex_width: Option<usize>) -> Self {
+        let width = hex_width
+            .or_else(|| {
+                io::stdout()
+                    .is_terminal()
+                    .then(|| std::env::var("COLUMNS").ok()?.parse().ok())
+                    .flatten()
+            })
+            .unwrap_or(DEFAULT_HEX_WIDTH);
+        Self {
+            write_count: 0,
+            read_count: 0,
+            num_panels: (width.saturating_sub(HEX_BASE_WIDTH) / HEX_PANEL_WIDTH).max(1) as u64,
+        }
+    }
+}
+
+impl Default for DebugDriver {
+    fn default() -> Self {
+        Self::new(None)
+    }
 }
 
 impl Driver for DebugDriver {
+    fn kind(&self) -> DriverKind {
+        DriverKind::Debug
+    }
+
     fn read(&mut self, buf: &mut [u8]) -> PrintyResult<usize> {
-        println!("P <- [{}]:", self.read_count);
+        // Like stdin below, a closed stdout is not worth failing the read over
+        writeln!(io::stdout(), "P <- [{}]:", self.read_count).ok();
 
         let mut input = String::new();
         io::stdin().read_line(&mut input).ok();
 
-        let hex_values: Result<Vec<u8>, _> = input
-            .trim()
-            .split_whitespace()
-            .map(|s| {
-                if s.starts_with("0x") || s.starts_with("0X") {
-                    u8::from_str_radix(&s[2..], 16)
-                } else {
-                    u8::from_str_radix(s, 16)
-                }
-            })
-            .collect();
-
-        match hex_values {
+        match parse_hex(&input) {
             Ok(values) => {
                 self.read_count += 1;
                 let bytes_to_copy = values.len().min(buf.len());
@@ -122,11 +314,24 @@ impl Driver for DebugDriver {
     }
 
     fn write(&mut self, data: &[u8]) -> PrintyResult<usize> {
-        println!("P -> [{}]:", self.write_count);
+        if data.is_empty() {
+            return Ok(0);
+        }
 
+        // Stdout may well be closed, e.g. when piped into `head`
         let mut handle = io::stdout().lock();
-        let mut hex_printer = hexyl::PrinterBuilder::new(&mut handle).build();
-        hex_printer.print_all(data).unwrap();
+        writeln!(handle, "P -> [{}]:", self.write_count)
+            .and_then(|_| {
+                hexyl::PrinterBuilder::new(&mut handle)
+                    .num_panels(self.num_panels)
+                    .build()
+                    .print_all(data)
+            })
+            .map_err(|e| PrintyError::Driver {
+                kind: DriverKind::Debug,
+                context: "Failed to print hex dump".to_string(),
+                source: Some(Box::new(e)),
+            })?;
 
         self.write_count += 1;
         Ok(data.len())
@@ -137,33 +342,257 @@ impl Driver for DebugDriver {
     }
 }
 
+/// Writes the command stream to any writer (e.g. a file or stdout) rather than to a printer,
+/// to inspect or post-process it. Nothing is ever read back.
+pub struct WriterDriver {
+    writer: Box<dyn io::Write>,
+}
+
+impl WriterDriver {
+    pub fn new(writer: Box<dyn io::Write>) -> Self {
+        Self { writer }
+    }
+
+    fn io_error(context: &str, e: io::Error) -> PrintyError {
+        PrintyError::Driver {
+            kind: DriverKind::Writer,
+            context: context.to_string(),
+            source: Some(Box::new(e)),
+        }
+    }
+}
+
+impl Driver for WriterDriver {
+    fn kind(&self) -> DriverKind {
+        DriverKind::Writer
+    }
+
+    fn read(&mut self, _buf: &mut [u8]) -> PrintyResult<usize> {
+        Ok(0)
+    }
+
+    fn write(&mut self, data: &[u8]) -> PrintyResult<usize> {
+        self.writer
+            .write_all(data)
+            .map_err(|e| Self::io_error("Failed to write output", e))?;
+        Ok(data.len())
+    }
+
+    fn drain(&mut self) -> PrintyResult<()> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> PrintyResult<()> {
+        self.writer
+            .flush()
+            .map_err(|e| Self::io_error("Failed to flush output", e))
+    }
+}
+
+/// Forwards everything written to the primary driver to a secondary one as well, e.g. a
+/// [`WriterDriver`] that keeps a byte-accurate log of each job. Reads and drains only go to the
+/// primary driver.
+pub struct TeeDriver {
+    primary: Box<dyn Driver>,
+    secondary: Box<dyn Driver>,
+    // Fail on errors of the secondary driver, which are otherwise only logged
+    strict: bool,
+}
+
+impl TeeDriver {
+    pub fn new(primary: Box<dyn Driver>, secondary: Box<dyn Driver>, strict: bool) -> Self {
+        Self {
+            primary,
+            secondary,
+            strict,
+        }
+    }
+
+    fn check_secondary<T>(&self, result: PrintyResult<T>) -> PrintyResult<()> {
+        match result {
+            Err(e) if !self.strict => {
+                warn!("Failed to write to the tee output - {e}");
+                Ok(())
+            }
+            result => result.map(|_| ()),
+        }
+    }
+}
+
+impl Driver for TeeDriver {
+    fn kind(&self) -> DriverKind {
+        self.primary.kind()
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> PrintyResult<usize> {
+        self.primary.read(buf)
+    }
+
+    fn write(&mut self, data: &[u8]) -> PrintyResult<usize> {
+        // Only what reached the primary driver is forwarded, as a failed write aborts the job
+        let w_len = self.primary.write(data)?;
+        let result = self.secondary.write(&data[..w_len]);
+        self.check_secondary(result)?;
+        Ok(w_len)
+    }
+
+    fn drain(&mut self) -> PrintyResult<()> {
+        self.primary.drain()
+    }
+
+    fn flush(&mut self) -> PrintyResult<()> {
+        self.primary.flush()?;
+        let result = self.secondary.flush();
+        self.check_secondary(result)
+    }
+}
+
+// Spaces that each level of a nested list is indented by
+const LIST_INDENT: usize = 2;
+
+// Spacing of the ticks of the calibration ruler, every other one being labeled
+const RULER_TICK_MM: u16 = 5;
+// Length of the vertical scale of the calibration ruler
+const RULER_FEED_MM: u16 = 50;
+
+// Font A characters that each level of nested blockquotes is indented by
+const BLOCKQUOTE_INDENT: u16 = 2;
+
+// Download graphics key that markdown images are defined under, each one replacing the last
+const MD_IMAGE_GRAPHICS_KEY: [u8; 2] = *b"MD";
+
+// Upper bound for the size of a single bulk write
+pub const MAX_CHUNK_SIZE: usize = 16 * 1024;
+
+// Size of each read when draining, a few full-speed (64 bytes) packets at once
+const DRAIN_BUF_LEN: usize = 256;
+
+// Leading bytes of each write that are logged, enough to identify a command without dumping
+// whole documents or rasters
+pub(crate) const LOG_CMD_MAX_LEN: usize = 16;
+
+// Bytes written between checks of `PrinterConfig::interrupt`, a fraction of a second of printing
+const INTERRUPT_CHECK_LEN: usize = 4096;
+
+// Interval at which USB devices are rescanned while waiting for the printer to show up
+const DEVICE_POLL_INTERVAL_MS: u64 = 250;
+
+/// Port that network printers accept raw `ESC/POS` on
+pub const DEFAULT_NETWORK_PORT: u16 = 9100;
+
+pub const DEFAULT_CONNECT_TIMEOUT_MS: u64 = 3000;
+
+// Same as the (fixed) timeout of USB transfers
+pub const DEFAULT_NETWORK_TIMEOUT_MS: u64 = 5000;
+
+/// How I/O operations that fail with a transient error (a stalled endpoint, or a write timing
+/// out) are retried. Other errors, e.g. a disconnected device, fail right away.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Number of attempts, including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry, which doubles with every further retry
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 2,
+            backoff: Duration::from_millis(CMD_PROC_DELAY_MS),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UsbOptions {
+    /// Size of each bulk write, defaults to the max packet size of the OUT endpoint. Some cheap
+    /// hubs choke on larger transfers, while others benefit from larger ones.
+    pub chunk_size: Option<usize>,
+    pub retry: RetryPolicy,
+    /// How long to wait for the device to show up (e.g. while it enumerates after being plugged
+    /// in) before giving up, not at all by default
+    pub wait_for_device: Duration,
+    /// Bus number (see [`rusb::Device::bus_number`]) that the device must be on, to tell apart
+    /// devices with the same VID/PID
+    pub bus: Option<u8>,
+    /// Address (see [`rusb::Device::address`]) that the device must have on its bus
+    pub address: Option<u8>,
+}
+
 pub struct UsbDriver {
     dev: DeviceHandle<Context>,
     in_ept_addr: u8,
     out_ept_addr: u8,
     io_timeout: Duration,
+    chunk_size: usize,
+    retry: RetryPolicy,
 }
 
 impl UsbDriver {
-    pub fn new(vid: u16, pid: u16) -> PrintyResult<Self> {
-        let usb_ctx = Context::new().unwrap();
-        let usb_devs = usb_ctx.devices().unwrap();
-
-        let print_dev = usb_devs
-            .iter()
-            .find(|dev| match dev.device_descriptor() {
-                Ok(dev_desc) => (dev_desc.vendor_id(), dev_desc.product_id()) == (vid, pid),
-                _ => false,
-            })
-            .ok_or(PrintyError::Driver {
+    pub fn new(vid: u16, pid: u16, options: &UsbOptions) -> PrintyResult<Self> {
+        let usb_ctx = Context::new().map_err(|e| PrintyError::Driver {
+            kind: DriverKind::Usb,
+            context: "Failed to initialize libusb".to_string(),
+            source: Some(Box::new(e)),
+        })?;
+        let wait_start = Instant::now();
+        let print_dev = loop {
+            let usb_devs = usb_ctx.devices().map_err(|e| PrintyError::Driver {
                 kind: DriverKind::Usb,
-                context: format!("Device (vid={vid:#04x}, pid={pid:#04x}) not found"),
-                source: None,
+                context: "Failed to list USB devices".to_string(),
+                source: Some(Box::new(e)),
             })?;
+            let found = usb_devs.iter().find(|dev| match dev.device_descriptor() {
+                Ok(dev_desc) => {
+                    (dev_desc.vendor_id(), dev_desc.product_id()) == (vid, pid)
+                        && options.bus.is_none_or(|bus| dev.bus_number() == bus)
+                        && options
+                            .address
+                            .is_none_or(|address| dev.address() == address)
+                }
+                _ => false,
+            });
+            match found {
+                Some(dev) => break dev,
+                None if wait_start.elapsed() < options.wait_for_device => {
+                    sleep(Duration::from_millis(DEVICE_POLL_INTERVAL_MS));
+                }
+                None => {
+                    let mut location = String::new();
+                    if let Some(bus) = options.bus {
+                        location.push_str(&format!(", bus={bus}"));
+                    }
+                    if let Some(address) = options.address {
+                        location.push_str(&format!(", address={address}"));
+                    }
+                    return Err(PrintyError::Driver {
+                        kind: DriverKind::Usb,
+                        context: format!(
+                            "Device (vid={vid:#04x}, pid={pid:#04x}{location}) not found"
+                        ),
+                        source: Some(Box::new(rusb::Error::NotFound)),
+                    });
+                }
+            }
+        };
 
-        let (in_ept_addr, out_ept_addr, if_num) = print_dev
+        let (in_ept_addr, (out_ept_addr, out_max_packet_size), if_num) = print_dev
             .active_config_descriptor()
-            .unwrap()
+            .map_err(|e| PrintyError::Driver {
+                kind: DriverKind::Usb,
+                context: format!(
+                    "Failed to read the configuration of device with VID: {vid:#04x}, PID: {pid:#04x}"
+                ),
+                source: Some(Box::new(e)),
+            })?
             .interfaces()
             .flat_map(|inf| inf.descriptors())
             .flat_map(|if_desc| {
@@ -175,7 +604,7 @@ impl UsbDriver {
                             in_ept = Some(ept.address());
                         }
                         (Direction::Out, TransferType::Bulk) => {
-                            out_ept = Some(ept.address());
+                            out_ept = Some((ept.address(), ept.max_packet_size()));
                         }
                         _ => {}
                     }
@@ -197,7 +626,11 @@ impl UsbDriver {
                 source: None
             })?;
 
-        let print_dev_handle = print_dev.open().expect("Failed to open device!");
+        let print_dev_handle = print_dev.open().map_err(|e| PrintyError::Driver {
+            kind: DriverKind::Usb,
+            context: format!("Failed to open device with VID: {vid:#04x}, PID: {pid:#04x}"),
+            source: Some(Box::new(e)),
+        })?;
         print_dev_handle
             .claim_interface(if_num)
             .map_err(|e| PrintyError::Driver {
@@ -212,41 +645,88 @@ impl UsbDriver {
             out_ept_addr,
             // NOTE: For now, default timeout seems sufficient, unless we need to allow user to configure it in the future
             io_timeout: Duration::from_secs(5),
+            chunk_size: options
+                .chunk_size
+                .unwrap_or(out_max_packet_size as usize)
+                .clamp(1, MAX_CHUNK_SIZE),
+            retry: options.retry.clone(),
         })
     }
 }
 
 impl UsbDriver {
-    fn _io_with_retry<F, T>(&self, ept_addr: u8, mut io_func: F) -> PrintyResult<T>
+    fn _io_with_retry<F, T>(&self, ept_addr: u8, io_func: F) -> PrintyResult<T>
     where
         F: FnMut() -> rusb::Result<T>,
     {
-        io_func().or_else(|e: rusb::Error| match e {
-            rusb::Error::Pipe => {
-                self.dev
-                    .clear_halt(ept_addr)
-                    .map_err(|e| PrintyError::Driver {
-                        kind: DriverKind::Usb,
-                        context: format!("Failed to clear halt on endpoint {ept_addr:#04x}"),
-                        source: Some(Box::new(e)),
-                    })?;
-                sleep(Duration::from_millis(CMD_PROC_DELAY_MS));
-                io_func().map_err(|e| PrintyError::Driver {
+        io_with_retry(&self.retry, ept_addr, io_func, || {
+            warn!("Clearing halt on endpoint {ept_addr:#04x}");
+            self.dev
+                .clear_halt(ept_addr)
+                .map_err(|e| PrintyError::Driver {
                     kind: DriverKind::Usb,
-                    context: format!("Failed to retry I/O operation on endpoint {ept_addr:#04x}"),
+                    context: format!("Failed to clear halt on endpoint {ept_addr:#04x}"),
                     source: Some(Box::new(e)),
                 })
-            }
-            _ => Err(PrintyError::Driver {
+        })
+    }
+}
+
+/// Runs `io_func` on endpoint `ept_addr` until it succeeds, retrying transient errors as
+/// `policy` allows. A stalled endpoint is cleared with `clear_halt` before it is retried.
+fn io_with_retry<F, C, T>(
+    policy: &RetryPolicy,
+    ept_addr: u8,
+    mut io_func: F,
+    mut clear_halt: C,
+) -> PrintyResult<T>
+where
+    F: FnMut() -> rusb::Result<T>,
+    C: FnMut() -> PrintyResult<()>,
+{
+    // IN endpoints have the direction bit set
+    let is_write = ept_addr & 0x80 == 0;
+    let mut attempt = 1;
+    loop {
+        let e = match io_func() {
+            Ok(result) => return Ok(result),
+            Err(e) => e,
+        };
+
+        let is_retryable = match e {
+            rusb::Error::Pipe => true,
+            // A read timing out usually just means that the printer has nothing to send
+            rusb::Error::Timeout => is_write,
+            _ => false,
+        };
+        if !is_retryable || attempt >= policy.max_attempts {
+            return Err(PrintyError::Driver {
                 kind: DriverKind::Usb,
-                context: format!("I/O error on endpoint {ept_addr:#04x}: {}", e),
+                context: format!(
+                    "I/O error on endpoint {ept_addr:#04x} (attempt {attempt} of {})",
+                    policy.max_attempts
+                ),
                 source: Some(Box::new(e)),
-            }),
-        })
+            });
+        }
+
+        warn!(
+            "I/O error on endpoint {ept_addr:#04x} ({e}), retrying (attempt {attempt} of {})",
+            policy.max_attempts
+        );
+        if e == rusb::Error::Pipe {
+            clear_halt()?;
+        }
+        sleep(policy.backoff(attempt));
+        attempt += 1;
     }
 }
 
 impl Driver for UsbDriver {
+    fn kind(&self) -> DriverKind {
+        DriverKind::Usb
+    }
+
     fn read(&mut self, buf: &mut [u8]) -> PrintyResult<usize> {
         self._io_with_retry(self.in_ept_addr, || {
             self.dev.read_bulk(self.in_ept_addr, buf, self.io_timeout)
@@ -254,147 +734,2107 @@ impl Driver for UsbDriver {
     }
 
     fn write(&mut self, data: &[u8]) -> PrintyResult<usize> {
-        // TODO: chunk the payload if it exceeds the receive buffer size
-        match self._io_with_retry(self.out_ept_addr, || {
-            self.dev
-                .write_bulk(self.out_ept_addr, data, self.io_timeout)
-        })? {
-            w_len if w_len == data.len() => Ok(w_len),
-            w_len => Err(PrintyError::Driver {
-                kind: DriverKind::Usb,
-                context: format!(
-                    "Partial write: expected {}, got {} - data: {:02x?}",
-                    data.len(),
-                    w_len,
-                    &data[..w_len]
-                ),
-                source: None,
-            }),
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        let mut total_w_len = 0;
+        for chunk in data.chunks(self.chunk_size) {
+            match self._io_with_retry(self.out_ept_addr, || {
+                self.dev
+                    .write_bulk(self.out_ept_addr, chunk, self.io_timeout)
+            })? {
+                w_len if w_len == chunk.len() => total_w_len += w_len,
+                w_len => {
+                    total_w_len += w_len;
+                    return Err(PrintyError::PartialWrite {
+                        kind: DriverKind::Usb,
+                        expected: data.len(),
+                        written: total_w_len,
+                    });
+                }
+            }
         }
+        Ok(total_w_len)
     }
 
     fn drain(&mut self) -> PrintyResult<()> {
-        let mut _buf = [0u8; 16];
+        let mut _buf = [0u8; DRAIN_BUF_LEN];
         while self.read(&mut _buf)? != 0 {}
         Ok(())
     }
 }
 
-pub struct Printer<D> {
-    pub driver: D,
+#[derive(Debug, Clone)]
+pub struct NetworkOptions {
+    /// How long to wait for the connection to each address the host resolves to. Zero waits
+    /// as long as the OS does.
+    pub connect_timeout: Duration,
+    /// How long a single read or write may block. Zero blocks indefinitely.
+    pub io_timeout: Duration,
+    /// Reconnects after the connection was dropped, see [`NetworkDriver`]
+    pub retry: RetryPolicy,
 }
 
-impl Printer<Box<dyn Driver>> {
-    pub fn usb(vid: u16, pid: u16) -> PrintyResult<Self> {
-        Self::new(Box::new(UsbDriver::new(vid, pid)?))
+impl Default for NetworkOptions {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_millis(DEFAULT_CONNECT_TIMEOUT_MS),
+            io_timeout: Duration::from_millis(DEFAULT_NETWORK_TIMEOUT_MS),
+            retry: RetryPolicy::default(),
+        }
     }
+}
 
-    pub fn debug() -> PrintyResult<Self> {
-        Self::new(Box::new(DebugDriver::default()))
-    }
+/// Talks to a network printer over a raw TCP connection (port 9100).
+///
+/// Network printers commonly drop idle connections, so a write that fails because the
+/// connection was dropped (e.g. reset) is retried on a new one. As there is no telling how much
+/// of the write reached the printer, all of it is sent again: delivery is at-least-once, and a
+/// reconnect in the middle of a write may print part of it twice. A write made shortly after
+/// the printer dropped the connection may also appear to succeed, and be lost.
+pub struct NetworkDriver {
+    addr: String,
+    stream: TcpStream,
+    options: NetworkOptions,
+}
 
-    pub fn new(driver: Box<dyn Driver>) -> PrintyResult<Self> {
-        let mut printer = Printer { driver };
-        printer.init()?;
-        Ok(printer)
+impl NetworkDriver {
+    /// Connects to `addr`, e.g. `192.168.1.50:9100`
+    pub fn new(addr: &str, options: &NetworkOptions) -> PrintyResult<Self> {
+        Ok(Self {
+            addr: addr.to_string(),
+            stream: Self::connect(addr, options)?,
+            options: options.clone(),
+        })
     }
 
-    fn init(&mut self) -> PrintyResult<&Self> {
-        /*
-          The printer (`TM-T88IV`) seems to transmit a 7-byte long data sequence (via the BULK endpoint)
-          upon powering on. This is not explicitly documented in the manual.
-
-          The sequence is as follows: [0x3B, 0x31, 0x0, 0x14, 0x0, 0x0, 0x0F]
-            - The first three bytes [0x3B, 0x31, 0x0] corresponds the closest to the transmitted
-              response of a power-off command (`DLE DC4 (fn=2)`).
-              - However, the identifier (`0x31`) does not match the expected value (`0x30`) according
-                to the specification.
-              - `TM-T88IV` does NOT support the power-off command (according to the `ESC/POS` manual),
-                which could a possible explanation for the mismatch.
-            - The remaining four bytes is the mysterious part, which seems to be a mixture of message
-              terminators and undocumented start-up/initialization sequences.
+    fn connect(addr: &str, options: &NetworkOptions) -> PrintyResult<TcpStream> {
+        let io_error = |context: String, e: io::Error| PrintyError::Driver {
+            kind: DriverKind::Network,
+            context,
+            source: Some(Box::new(e)),
+        };
 
-          If the printer is powered on with an OFFLINE state (e.g. cover is open, paper is out), there
-          will be additional 4 bytes of `ASB` (Automatic Status Back) message (which seems to also
-          suggest that `ASB` is enabled by default).
+        let socket_addrs = addr
+            .to_socket_addrs()
+            .map_err(|e| io_error(format!("Failed to resolve {addr}"), e))?;
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "no addresses");
+        for socket_addr in socket_addrs {
+            let stream = if options.connect_timeout.is_zero() {
+                TcpStream::connect(socket_addr)
+            } else {
+                TcpStream::connect_timeout(&socket_addr, options.connect_timeout)
+            };
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    last_err = e;
+                    continue;
+                }
+            };
 
-          As such, we will drain these initial bytes to avoid any issues with message backlogging
-          (transmitted data is only cleared after host reads it).
-        */
-        self.driver.drain()?;
-        self.driver.write(CMD_INIT)?;
-        // NOTE: Only works (reliably) if the printer (`TM-T88IV`) is powered on with an ONLINE state
-        // Else, `ASB` sequences will still be transmitted
-        self.driver.write(CMD_DISABLE_ASB)?;
-        Ok(self)
+            let io_timeout = Some(options.io_timeout).filter(|timeout| !timeout.is_zero());
+            stream
+                .set_read_timeout(io_timeout)
+                .and_then(|_| stream.set_write_timeout(io_timeout))
+                // Commands are often short and awaited (e.g. status requests)
+                .and_then(|_| stream.set_nodelay(true))
+                .map_err(|e| io_error(format!("Failed to set up the connection to {addr}"), e))?;
+            return Ok(stream);
+        }
+        Err(io_error(format!("Failed to connect to {addr}"), last_err))
     }
 
-    pub fn status(&mut self) -> Option<PrinterStatus> {
-        let batched_status_cmds = [
-            CMD_RT_STATUS(RtStatusReq::PrinterStatus),
-            CMD_RT_STATUS(RtStatusReq::OfflineCause),
-            CMD_RT_STATUS(RtStatusReq::ErrorCause),
-            CMD_RT_STATUS(RtStatusReq::PaperStatus),
-        ]
-        .concat();
-        self.driver.write(batched_status_cmds.as_slice()).unwrap();
+    fn is_dropped(e: &io::Error) -> bool {
+        matches!(
+            e.kind(),
+            io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::NotConnected
+        )
+    }
+}
 
-        sleep(Duration::from_millis(CMD_PROC_DELAY_MS));
+impl Driver for NetworkDriver {
+    fn kind(&self) -> DriverKind {
+        DriverKind::Network
+    }
 
-        let mut buf = [0u8; 4];
-        match self.driver.read(&mut buf) {
-            Ok(len) if len == buf.len() => PrinterStatus::from_bytes(&buf),
-            _ => None,
+    fn read(&mut self, buf: &mut [u8]) -> PrintyResult<usize> {
+        match self.stream.read(buf) {
+            Ok(r_len) => Ok(r_len),
+            // The printer has nothing to send
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(0)
+            }
+            Err(e) => Err(PrintyError::Driver {
+                kind: DriverKind::Network,
+                context: format!("Failed to read from {}", self.addr),
+                source: Some(Box::new(e)),
+            }),
         }
     }
 
-    pub fn cut(&mut self) -> PrintyResult<&mut Self> {
-        self.driver.write(CMD_CUT)?;
-        Ok(self)
-    }
+    fn write(&mut self, data: &[u8]) -> PrintyResult<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
 
-    pub fn print(&mut self, data: &str) -> PrintyResult<&mut Self> {
-        self.driver.write(data.as_bytes())?;
-        Ok(self)
+        let mut attempt = 1;
+        loop {
+            let e = match self.stream.write_all(data) {
+                Ok(()) => return Ok(data.len()),
+                Err(e) => e,
+            };
+            if !Self::is_dropped(&e) || attempt >= self.options.retry.max_attempts {
+                return Err(PrintyError::Driver {
+                    kind: DriverKind::Network,
+                    context: format!(
+                        "Failed to write to {} (attempt {attempt} of {})",
+                        self.addr, self.options.retry.max_attempts
+                    ),
+                    source: Some(Box::new(e)),
+                });
+            }
+
+            warn!(
+                "Connection to {} dropped ({e}), reconnecting (attempt {attempt} of {})",
+                self.addr, self.options.retry.max_attempts
+            );
+            sleep(self.options.retry.backoff(attempt));
+            self.stream = Self::connect(&self.addr, &self.options)?;
+            attempt += 1;
+        }
     }
 
-    pub fn print_md(&mut self, data: &str) -> PrintyResult<&mut Self> {
-        self.driver.write(&EscposMarkdown.compile(data)?)?;
-        Ok(self)
+    fn drain(&mut self) -> PrintyResult<()> {
+        // Without blocking, as the printer may well have nothing to send
+        let io_error = |e: io::Error| PrintyError::Driver {
+            kind: DriverKind::Network,
+            context: "Failed to drain pending input".to_string(),
+            source: Some(Box::new(e)),
+        };
+        self.stream.set_nonblocking(true).map_err(io_error)?;
+        let mut _buf = [0u8; DRAIN_BUF_LEN];
+        let drained = loop {
+            match self.stream.read(&mut _buf) {
+                Ok(0) => break Ok(()),
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        self.stream.set_nonblocking(false).map_err(io_error)?;
+        drained.map_err(io_error)
     }
 }
 
-struct EscposMarkdown;
+/// Command used to query the printer status
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum StatusMethod {
+    /// Real-time status (`DLE EOT`), which also reports the offline cause and errors
+    DleEot,
+    /// Paper sensor status (`GS r`), which is more reliable on some printers
+    GsR,
+}
+
+/// Status query of a [`StatusMethod`], as the requests to send one at a time and the parsing
+/// of their replies. [`Printer::status`] and its async counterpart only do the I/O, so that both
+/// query the printer in the same way.
+pub(crate) struct StatusQuery {
+    method: StatusMethod,
+    ignore_near_end: bool,
+    // Status bytes parsed from the replies so far
+    bytes: Vec<u8>,
+}
+
+impl StatusQuery {
+    pub(crate) fn new(config: &PrinterConfig) -> Self {
+        Self {
+            method: config.status_method,
+            ignore_near_end: config.ignore_near_end,
+            bytes: Vec::new(),
+        }
+    }
+
+    /// Next request to send and how long to wait for its reply, or `None` once every request
+    /// was answered
+    pub(crate) fn next_request(&self) -> Option<(Vec<u8>, Duration)> {
+        match self.method {
+            // Requests are sent one at a time, so that each reply can be told apart even if the
+            // printer coalesces replies or interleaves them with other data
+            StatusMethod::DleEot => RT_STATUS_REQS.get(self.bytes.len()).map(|&req| {
+                (
+                    CMD_RT_STATUS(req),
+                    Duration::from_millis(RT_STATUS_REPLY_DELAY_MS),
+                )
+            }),
+            StatusMethod::GsR => self.bytes.is_empty().then(|| {
+                (
+                    CMD_TRANSMIT_STATUS(TransmitStatusReq::PaperSensor),
+                    Duration::from_millis(CMD_PROC_DELAY_MS),
+                )
+            }),
+        }
+    }
+
+    /// Takes the reply to the last request, returning `None` if it holds no valid status (which
+    /// fails the query)
+    pub(crate) fn reply(&mut self, reply: &[u8]) -> Option<()> {
+        let byte = match self.method {
+            StatusMethod::DleEot => rt_status_byte(reply),
+            StatusMethod::GsR => transmit_status_byte(reply),
+        }?;
+        self.bytes.push(byte);
+        Some(())
+    }
+
+    /// Status reported by the replies, once every request was answered
+    pub(crate) fn status(&self) -> Option<PrinterStatus> {
+        let status = match self.method {
+            StatusMethod::DleEot => {
+                PrinterStatus::from_bytes(self.bytes.as_slice().try_into().ok()?)
+            }
+            StatusMethod::GsR => PrinterStatus::from_transmit_byte(*self.bytes.first()?),
+        }?;
+        Some(if self.ignore_near_end {
+            status.ignoring_near_end()
+        } else {
+            status
+        })
+    }
+}
+
+/// How markdown emphasis (`*text*`) is printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ItalicStyle {
+    /// True italics (`ESC 4`), which only some `ESC/POS` clones support (see
+    /// [`Feature::Italic`])
+    Italic,
+    Underline,
+}
+
+/// How markdown strong emphasis (`**text**`) is printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StrongStyle {
+    /// Emphasized (`ESC E`), which is subtle on some thermal paper
+    Bold,
+    /// White on black (`GS B`)
+    Reverse,
+}
+
+/// How markdown blockquotes (`> text`) are set apart from the text around them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BlockquoteStyle {
+    /// Indented by a left margin (`GS L`), which lines wrapped by the printer keep
+    Indent,
+    /// In the smaller Font B (`ESC M`), which fits more characters on each line
+    Small,
+    Both,
+}
+
+impl BlockquoteStyle {
+    fn indents(&self) -> bool {
+        matches!(self, BlockquoteStyle::Indent | BlockquoteStyle::Both)
+    }
+
+    fn is_small(&self) -> bool {
+        matches!(self, BlockquoteStyle::Small | BlockquoteStyle::Both)
+    }
+}
+
+/// Line ending sent to the printer in plain text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LineEnding {
+    Lf,
+    Cr,
+    Crlf,
+}
+
+impl LineEnding {
+    pub fn sequence(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Cr => "\r",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+}
+
+/// Horizontal alignment of printed lines (`ESC a`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+impl From<Align> for JustifyReq {
+    fn from(align: Align) -> Self {
+        match align {
+            Align::Left => JustifyReq::Left,
+            Align::Center => JustifyReq::Center,
+            Align::Right => JustifyReq::Right,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PrinterConfig {
+    pub usb: UsbOptions,
+    pub network: NetworkOptions,
+    /// Number of characters that fit on a line
+    pub columns: usize,
+    /// Number of Font B characters that fit on a line, for text set in it (e.g. blockquotes in
+    /// [`BlockquoteStyle::Small`])
+    pub font_b_columns: usize,
+    pub line_height_mm: f32,
+    /// Drain any pending bytes transmitted by the printer before initializing
+    pub drain_on_init: bool,
+    /// Initialization sequence, `ESC @` by default. Some clones need a custom sequence (e.g. to
+    /// also select a code page and font), but a bad one can leave the printer unresponsive.
+    pub init_cmd: Vec<u8>,
+    /// Cut sequence, `GS V` (partial cut after feeding to the cutter) by default. Some clones
+    /// cut with `ESC i` (full cut) or `ESC m` (partial cut) instead. If empty,
+    /// [`Printer::cut`] only feeds [`PrinterConfig::feed_before_cut_mm`].
+    pub cut_cmd: Vec<u8>,
+    /// Paper to feed past the cutting position (in millimeters, up to
+    /// [`crate::escpos::MAX_CUT_FEED_MM`]) as part of `GS V 66 n`, which replaces
+    /// [`PrinterConfig::cut_cmd`] if set. Unlike [`PrinterConfig::feed_before_cut_mm`], the
+    /// feed happens within the cut command.
+    pub cut_feed_mm: Option<f32>,
+    /// Skip initializing the printer on creation, to leave its state exactly as the bytes
+    /// sent to it set it
+    pub skip_init: bool,
+    /// Disable `ASB` (Automatic Status Back) on init
+    pub disable_asb: bool,
+    pub status_method: StatusMethod,
+    /// Report paper as adequate rather than near its end, for printers without a roll
+    /// near-end sensor that report a spurious near-end status
+    pub ignore_near_end: bool,
+    /// Reorder right-to-left (e.g. Arabic, Hebrew) runs into visual order, as printers print
+    /// everything left-to-right
+    pub bidi: bool,
+    /// Keep runs of blank lines between markdown blocks, which are otherwise collapsed into
+    /// a single blank line. Line breaks within a paragraph are always kept, rather than being
+    /// reflowed into spaces.
+    pub preserve_lines: bool,
+    /// Send `\r\n` line endings as-is in plain text, instead of normalizing them to `\n`
+    pub keep_cr: bool,
+    /// Sequence that line feeds in plain text are sent as, for printers that only advance the
+    /// paper on a `CR`. Applied after [`PrinterConfig::keep_cr`], whose kept `CR`s are sent in
+    /// addition. Markdown is unaffected, as its line feeds are emitted by the compiler.
+    pub line_ending: LineEnding,
+    /// Default alignment of markdown documents, which `<!-- align:... -->` directives in the
+    /// document override until the matching `<!-- /align -->`
+    pub align: Align,
+    /// Paper to feed (in millimeters) before each cut, on top of the feed to the cutter
+    pub feed_before_cut_mm: f32,
+    /// Encoding of printed text, which is sent as UTF-8 by default
+    pub encoding: Encoding,
+    /// What to print in place of characters that the code page lacks, when transcoding
+    pub glyph_fallback: GlyphFallback,
+    /// Font that [`GlyphFallback::Raster`] draws characters with, without which it prints `?`
+    pub glyph_font: Option<Arc<GlyphFont>>,
+    /// End markdown blocks with a single line feed, instead of leaving a blank line after them
+    pub compact: bool,
+    pub qr_model: QrModel,
+    pub italic_style: ItalicStyle,
+    pub strong_style: StrongStyle,
+    pub blockquote_style: BlockquoteStyle,
+    /// Print markdown headings (levels 1 to 3) in the heavier shadow style of
+    /// [`Printer::print_shadow`], which needs [`Feature::ReverseFeed`]
+    pub shadow_headings: bool,
+    /// Magnification of printed graphics, whose width in dots is multiplied accordingly
+    pub image_density: ImageDensity,
+    /// Directory that relative paths of markdown images are resolved against, the current
+    /// directory if unset
+    pub image_base_dir: Option<PathBuf>,
+    /// Width (in columns) of the debug driver's hex dump, the terminal's by default
+    pub hex_width: Option<usize>,
+    /// Printable width of the paper, in millimeters. Graphics are scaled down and separators
+    /// shortened to fit within it, as anything beyond is cut off by the physical margin.
+    pub max_width_mm: f32,
+    /// End jobs printed by [`Printer::print_and_cut`] and [`Printer::print_md_and_cut`] with a
+    /// footer of their hash, see [`Printer::print_hash_footer`]
+    pub footer_hash: bool,
+    /// Commands sent before each job printed by the `*_and_cut` methods, e.g. a shop's name
+    /// and address compiled from markdown by [`Printer::compile_md`]
+    pub job_header: Vec<u8>,
+    /// Like `job_header`, but sent after each job, before its hash footer and the cut
+    pub job_footer: Vec<u8>,
+    /// Stops writes once set (e.g. by a `SIGINT` handler), failing them with
+    /// [`PrintyError::Interrupted`]. Long writes are checked every few kilobytes, see
+    /// [`Printer::abort_job`] to end the job cleanly.
+    pub interrupt: Option<Arc<AtomicBool>>,
+    /// Underline URLs in the text of markdown documents (e.g. `https://example.com`), which
+    /// are otherwise printed like the text around them
+    pub autolink: bool,
+    /// Apply the settings of a markdown document's front matter (see [`crate::front_matter`])
+    /// over this configuration. The front matter is stripped from the printout either way.
+    pub apply_front_matter: bool,
+}
+
+impl PrinterConfig {
+    /// Commands that initialize the printer, sent when it is connected
+    pub(crate) fn init_sequence(&self) -> Vec<u8> {
+        if self.disable_asb {
+            // NOTE: Only works (reliably) if the printer (`TM-T88IV`) is powered on with an ONLINE state
+            // Else, `ASB` sequences will still be transmitted
+            [&self.init_cmd, CMD_DISABLE_ASB].concat()
+        } else {
+            self.init_cmd.clone()
+        }
+    }
+
+    /// Prepares plain text for printing according to the configuration
+    pub(crate) fn prepare_text<'a>(&self, data: &'a str) -> Cow<'a, str> {
+        let text = if self.keep_cr {
+            Cow::Borrowed(data)
+        } else {
+            // A `CR` triggers a carriage return on some printers, which double prints or misaligns
+            // lines of text files with Windows (`CRLF`) line endings
+            Cow::Owned(data.replace("\r\n", "\n"))
+        };
+
+        let text = if self.bidi {
+            Cow::Owned(reorder_bidi(&text))
+        } else {
+            text
+        };
+
+        match self.line_ending {
+            LineEnding::Lf => text,
+            ending => Cow::Owned(text.replace('\n', ending.sequence())),
+        }
+    }
+
+    /// Sequence that cuts the paper, see [`PrinterConfig::cut_feed_mm`]
+    pub(crate) fn cut_sequence(&self) -> Vec<u8> {
+        match self.cut_feed_mm {
+            Some(mm) => CMD_CUT_FEED(cut_feed_units(mm, VERTICAL_MOTION_UNITS_PER_INCH)),
+            None => self.cut_cmd.clone(),
+        }
+    }
+
+    /// Printable width in dots, see [`PrinterConfig::max_width_mm`]
+    pub(crate) fn max_width_dots(&self) -> u16 {
+        (self.max_width_mm * DOTS_PER_MM) as u16
+    }
+
+    /// Encodes prepared text according to the configured encoding, selecting the code page
+    /// first if it is transcoded
+    pub(crate) fn encode_text(&self, text: &str) -> Vec<u8> {
+        match self.encoding.code_page(text) {
+            Some(page) => [
+                CMD_CODEPAGE(page.escpos_page()),
+                glyph::encode(page, text, self.glyph_fallback, self.glyph_font.as_deref()),
+            ]
+            .concat(),
+            None => text.as_bytes().to_vec(),
+        }
+    }
+}
+
+impl fmt::Display for PrinterConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn name(value: &impl clap::ValueEnum) -> String {
+            value
+                .to_possible_value()
+                .map_or_else(String::new, |value| value.get_name().to_string())
+        }
+        fn hex(bytes: &[u8]) -> String {
+            let hex = bytes.iter().map(|b| format!("{b:02x}")).collect::<Vec<_>>();
+            if hex.is_empty() {
+                "(none)".to_string()
+            } else {
+                hex.join(" ")
+            }
+        }
+        fn yes_no(enabled: bool) -> &'static str {
+            if enabled { "yes" } else { "no" }
+        }
+
+        writeln!(f, "Paper:")?;
+        writeln!(f, "  Columns: {}", self.columns)?;
+        writeln!(f, "  Columns (Font B): {}", self.font_b_columns)?;
+        writeln!(f, "  Printable width: {:.1}mm", self.max_width_mm)?;
+        writeln!(f, "  Line height: {:.1}mm", self.line_height_mm)?;
+        writeln!(f, "Text:")?;
+        writeln!(f, "  Alignment: {}", name(&self.align))?;
+        writeln!(f, "  Emphasis: {}", name(&self.italic_style))?;
+        writeln!(f, "  Strong emphasis: {}", name(&self.strong_style))?;
+        writeln!(f, "  Blockquotes: {}", name(&self.blockquote_style))?;
+        writeln!(f, "  Shadow headings: {}", yes_no(self.shadow_headings))?;
+        writeln!(f, "  Reorder right-to-left text: {}", yes_no(self.bidi))?;
+        writeln!(f, "  Keep blank lines: {}", yes_no(self.preserve_lines))?;
+        writeln!(f, "  Compact blocks: {}", yes_no(self.compact))?;
+        writeln!(f, "  Underline URLs: {}", yes_no(self.autolink))?;
+        writeln!(f, "  Front matter: {}", yes_no(self.apply_front_matter))?;
+        writeln!(f, "Encoding:")?;
+        writeln!(f, "  Encoding: {}", name(&self.encoding))?;
+        match (self.glyph_fallback, &self.glyph_font) {
+            (GlyphFallback::Raster, None) => writeln!(f, "  Glyph fallback: raster (no font)")?,
+            (fallback, _) => writeln!(f, "  Glyph fallback: {}", name(&fallback))?,
+        }
+        writeln!(f, "  Keep CRLF: {}", yes_no(self.keep_cr))?;
+        writeln!(f, "  Line endings: {}", name(&self.line_ending))?;
+        writeln!(f, "Graphics:")?;
+        writeln!(f, "  QR code model: {}", name(&self.qr_model))?;
+        writeln!(f, "  Image density: {}", name(&self.image_density))?;
+        match &self.image_base_dir {
+            Some(dir) => writeln!(f, "  Image directory: {}", dir.display())?,
+            None => writeln!(f, "  Image directory: (current directory)")?,
+        }
+        writeln!(f, "Cut:")?;
+        writeln!(f, "  Hash footer: {}", yes_no(self.footer_hash))?;
+        writeln!(f, "  Feed before cut: {:.1}mm", self.feed_before_cut_mm)?;
+        writeln!(f, "  Cut sequence: {}", hex(&self.cut_sequence()))?;
+        writeln!(f, "Connection:")?;
+        writeln!(f, "  Init sequence: {}", hex(&self.init_cmd))?;
+        writeln!(f, "  Initialize: {}", yes_no(!self.skip_init))?;
+        writeln!(f, "  Drain on init: {}", yes_no(self.drain_on_init))?;
+        writeln!(f, "  Disable ASB: {}", yes_no(self.disable_asb))?;
+        writeln!(f, "  Status method: {}", name(&self.status_method))?;
+        writeln!(f, "  Ignore near-end: {}", yes_no(self.ignore_near_end))?;
+        match self.usb.chunk_size {
+            Some(size) => writeln!(f, "  Chunk size: {size}")?,
+            None => writeln!(f, "  Chunk size: max packet size")?,
+        }
+        writeln!(
+            f,
+            "  Retries: {} (backoff {}ms)",
+            self.usb.retry.max_attempts.saturating_sub(1),
+            self.usb.retry.backoff.as_millis()
+        )?;
+        writeln!(
+            f,
+            "  Wait for device: {}ms",
+            self.usb.wait_for_device.as_millis()
+        )?;
+        writeln!(
+            f,
+            "  Network timeouts: connect {}ms, I/O {}ms",
+            self.network.connect_timeout.as_millis(),
+            self.network.io_timeout.as_millis()
+        )
+    }
+}
+
+/// Returns the image (inline or by reference) of a paragraph that consists of nothing else
+/// (besides whitespace)
+fn sole_image(para: &mdast::Paragraph) -> Option<&mdast::Node> {
+    let mut children = para
+        .children
+        .iter()
+        .filter(|child| !matches!(child, mdast::Node::Text(text) if text.value.trim().is_empty()));
+    match (children.next(), children.next()) {
+        (Some(image @ (mdast::Node::Image(_) | mdast::Node::ImageReference(_))), None) => {
+            Some(image)
+        }
+        _ => None,
+    }
+}
+
+/// Parses markdown (without front matter) into the tree that [`EscposMarkdown`] compiles, with
+/// the GFM extensions it supports (footnotes, tables and task lists)
+pub fn parse_md(md_str: &str) -> PrintyResult<mdast::Node> {
+    let parse_opts = markdown::ParseOptions {
+        constructs: markdown::Constructs {
+            gfm_footnote_definition: true,
+            gfm_label_start_footnote: true,
+            gfm_table: true,
+            gfm_task_list_item: true,
+            ..markdown::Constructs::default()
+        },
+        ..markdown::ParseOptions::default()
+    };
+    to_mdast(md_str, &parse_opts).map_err(|e| PrintyError::Parse {
+        context: format!("Failed to parse markdown - {e}"),
+        source: None,
+    })
+}
+
+/// Finds the URLs in `text`, i.e. words (or their tails, e.g. after a `(`) that start with
+/// `http://`, `https://` or `www.`, leaving out trailing punctuation
+fn find_urls(text: &str) -> Vec<Range<usize>> {
+    const URL_PREFIXES: [&str; 3] = ["https://", "http://", "www."];
+    const TRAILING_PUNCTUATION: &[char] = &['.', ',', ';', ':', '!', '?', '\'', '"', ')', ']', '>'];
+
+    let mut urls = Vec::new();
+    let mut word_start = None;
+    for (idx, ch) in text.char_indices().chain([(text.len(), ' ')]) {
+        if !ch.is_whitespace() {
+            word_start.get_or_insert(idx);
+            continue;
+        }
+        let Some(start) = word_start.take() else {
+            continue;
+        };
+
+        let word = &text[start..idx];
+        let url_start = URL_PREFIXES
+            .iter()
+            .filter_map(|prefix| {
+                word.match_indices(prefix).map(|(pos, _)| pos).find(|&pos| {
+                    // Not in the middle of a word, e.g. `awww.`
+                    !word[..pos]
+                        .chars()
+                        .next_back()
+                        .is_some_and(char::is_alphanumeric)
+                })
+            })
+            .min();
+        if let Some(url_start) = url_start {
+            let url = word[url_start..].trim_end_matches(TRAILING_PUNCTUATION);
+            // A bare prefix is not a URL
+            if !URL_PREFIXES.contains(&url) {
+                urls.push(start + url_start..start + url_start + url.len());
+            }
+        }
+    }
+    urls
+}
+
+/// Reorders each paragraph (line) of `text` from logical to visual order
+fn reorder_bidi(text: &str) -> String {
+    let bidi_info = BidiInfo::new(text, None);
+    let mut reordered = String::with_capacity(text.len());
+    for para in &bidi_info.paragraphs {
+        // The paragraph separator takes the paragraph's level (rule L1), so it's kept out of
+        // the reordering to stay at the end of its line
+        let line = text[para.range.clone()]
+            .trim_end_matches(|c| unicode_bidi::bidi_class(c) == BidiClass::B);
+        let line_end = para.range.start + line.len();
+        if !line.is_empty() {
+            reordered.push_str(&bidi_info.reorder_line(para, para.range.start..line_end));
+        }
+        reordered.push_str(&text[line_end..para.range.end]);
+    }
+    reordered
+}
+
+/// Prints `line` (already encoded), feeds the paper back by a dot (`ESC K`), and prints it
+/// again one dot to the right (`ESC $`), for a heavier shadow style
+fn overprint_shadow(line: &[u8]) -> Vec<u8> {
+    [line, &CMD_REVERSE_FEED_UNITS(1), &CMD_ABS_POSITION(1), line].concat()
+}
+
+impl Default for PrinterConfig {
+    fn default() -> Self {
+        Self {
+            usb: UsbOptions::default(),
+            network: NetworkOptions::default(),
+            columns: DEFAULT_COLUMNS,
+            font_b_columns: DEFAULT_FONT_B_COLUMNS,
+            line_height_mm: DEFAULT_LINE_HEIGHT_MM,
+            drain_on_init: true,
+            init_cmd: CMD_INIT.to_vec(),
+            cut_cmd: CMD_CUT.to_vec(),
+            cut_feed_mm: None,
+            skip_init: false,
+            disable_asb: true,
+            status_method: StatusMethod::DleEot,
+            ignore_near_end: false,
+            bidi: false,
+            preserve_lines: false,
+            keep_cr: false,
+            line_ending: LineEnding::Lf,
+            align: Align::Left,
+            feed_before_cut_mm: 0.0,
+            encoding: Encoding::Utf8,
+            glyph_fallback: GlyphFallback::Question,
+            glyph_font: None,
+            compact: false,
+            qr_model: QrModel::Model2,
+            italic_style: ItalicStyle::Underline,
+            strong_style: StrongStyle::Bold,
+            blockquote_style: BlockquoteStyle::Indent,
+            shadow_headings: false,
+            image_density: ImageDensity::Normal,
+            image_base_dir: None,
+            hex_width: None,
+            max_width_mm: DEFAULT_PRINT_WIDTH_MM,
+            footer_hash: false,
+            job_header: Vec::new(),
+            job_footer: Vec::new(),
+            interrupt: None,
+            autolink: false,
+            apply_front_matter: true,
+        }
+    }
+}
+
+/// Estimated cost of a job, see [`Printer::estimate_job`]
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct JobEstimate {
+    pub length_mm: f32,
+    pub duration_s: f32,
+    /// Print speed that the duration was estimated with
+    pub speed_mm_s: f32,
+    /// Size of the job, without the initialization and cut
+    pub bytes: usize,
+}
+
+/// Geometry of a line as configured, for laying out content (see [`Printer::metrics`])
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrinterMetrics {
+    /// Font A characters that fit on a line, the configured [`PrinterConfig::columns`] unless
+    /// fewer fit the printable width
+    pub font_a_columns: usize,
+    /// Like `font_a_columns`, for Font B (see [`PrinterConfig::font_b_columns`])
+    pub font_b_columns: usize,
+    /// Width of a Font A character (including spacing), in dots
+    pub font_a_width_dots: u16,
+    /// Width of a Font B character (including spacing), in dots
+    pub font_b_width_dots: u16,
+    pub dots_per_mm: f32,
+    /// Printable width of a line in dots, see [`PrinterConfig::max_width_mm`]
+    pub print_width_dots: u16,
+    pub print_width_mm: f32,
+}
+
+impl From<&PrinterConfig> for PrinterMetrics {
+    fn from(config: &PrinterConfig) -> Self {
+        let print_width_dots = config.max_width_dots();
+        Self {
+            font_a_columns: config
+                .columns
+                .min((print_width_dots / FONT_A_WIDTH_DOTS) as usize),
+            font_b_columns: config
+                .font_b_columns
+                .min((print_width_dots / FONT_B_WIDTH_DOTS) as usize),
+            font_a_width_dots: FONT_A_WIDTH_DOTS,
+            font_b_width_dots: FONT_B_WIDTH_DOTS,
+            dots_per_mm: DOTS_PER_MM,
+            print_width_dots,
+            print_width_mm: config.max_width_mm,
+        }
+    }
+}
+
+/// What a [`PrinterBuilder`] connects the printer through
+pub enum Transport {
+    Usb {
+        vid: u16,
+        pid: u16,
+    },
+    /// `host:port` of a network printer, see [`NetworkDriver`]
+    Network {
+        addr: String,
+    },
+    /// See [`DebugDriver`]
+    Debug,
+    /// See [`WriterDriver`]
+    Writer(Box<dyn io::Write>),
+    Driver(Box<dyn Driver>),
+}
+
+/// Configures and connects a [`Printer`], e.g.
+///
+/// ```no_run
+/// # use printy::printer::{Printer, Transport};
+/// let printer = Printer::builder(Transport::Usb { vid: 0x04b8, pid: 0x0202 })
+///     .columns(48)
+///     .drain_on_init(false)
+///     .build()?;
+/// # Ok::<(), printy::printer::PrintyError>(())
+/// ```
+///
+/// Options without a setter of their own are set through [`PrinterBuilder::config`].
+pub struct PrinterBuilder {
+    transport: Transport,
+    config: PrinterConfig,
+    tee: Option<(Box<dyn io::Write>, bool)>,
+}
+
+impl PrinterBuilder {
+    pub fn new(transport: Transport) -> Self {
+        Self {
+            transport,
+            config: PrinterConfig::default(),
+            tee: None,
+        }
+    }
+
+    /// Replaces the whole configuration, including what was set by the other setters so far
+    pub fn config(mut self, config: PrinterConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Chunking, retries and how long to wait for the device, for [`Transport::Usb`]
+    pub fn usb_options(mut self, usb: UsbOptions) -> Self {
+        self.config.usb = usb;
+        self
+    }
+
+    /// Timeouts and reconnects, for [`Transport::Network`]
+    pub fn network_options(mut self, network: NetworkOptions) -> Self {
+        self.config.network = network;
+        self
+    }
+
+    pub fn columns(mut self, columns: usize) -> Self {
+        self.config.columns = columns;
+        self
+    }
+
+    pub fn font_b_columns(mut self, columns: usize) -> Self {
+        self.config.font_b_columns = columns;
+        self
+    }
+
+    /// Printable width of the paper, see [`PrinterConfig::max_width_mm`]
+    pub fn max_width_mm(mut self, max_width_mm: f32) -> Self {
+        self.config.max_width_mm = max_width_mm;
+        self
+    }
+
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.config.encoding = encoding;
+        self
+    }
+
+    pub fn init_cmd(mut self, init_cmd: Vec<u8>) -> Self {
+        self.config.init_cmd = init_cmd;
+        self
+    }
+
+    pub fn skip_init(mut self, skip_init: bool) -> Self {
+        self.config.skip_init = skip_init;
+        self
+    }
+
+    pub fn drain_on_init(mut self, drain_on_init: bool) -> Self {
+        self.config.drain_on_init = drain_on_init;
+        self
+    }
+
+    pub fn disable_asb(mut self, disable_asb: bool) -> Self {
+        self.config.disable_asb = disable_asb;
+        self
+    }
+
+    /// Copies everything sent to the printer to `writer`, see [`TeeDriver`]
+    pub fn tee(mut self, writer: Box<dyn io::Write>, strict: bool) -> Self {
+        self.tee = Some((writer, strict));
+        self
+    }
+
+    /// Connects to the printer and, unless skipped, initializes it
+    pub fn build(self) -> PrintyResult<Printer<Box<dyn Driver>>> {
+        let driver: Box<dyn Driver> = match self.transport {
+            Transport::Usb { vid, pid } => Box::new(UsbDriver::new(vid, pid, &self.config.usb)?),
+            Transport::Network { addr } => {
+                Box::new(NetworkDriver::new(&addr, &self.config.network)?)
+            }
+            Transport::Debug => Box::new(DebugDriver::new(self.config.hex_width)),
+            Transport::Writer(writer) => Box::new(WriterDriver::new(writer)),
+            Transport::Driver(driver) => driver,
+        };
+        let driver = match self.tee {
+            Some((writer, strict)) => Box::new(TeeDriver::new(
+                driver,
+                Box::new(WriterDriver::new(writer)),
+                strict,
+            )),
+            None => driver,
+        };
+        Printer::new(driver, self.config)
+    }
+}
+
+/// Sends jobs and commands to a printer through a [`Driver`].
+///
+/// The paper is never cut implicitly: printing methods (e.g. [`Printer::print`],
+/// [`Printer::print_md`]) leave it uncut, and only [`Printer::cut`] and the methods named
+/// `*_and_cut` cut it. Jobs can thus be composed freely before a single explicit cut.
+pub struct Printer<D> {
+    pub driver: D,
+    pub config: PrinterConfig,
+    bytes_written: usize,
+    capabilities: Option<Capabilities>,
+    // Whether the model was probed, even if it could not be identified
+    probed: bool,
+    // Keys of the download graphics defined since init
+    stored_graphics: HashSet<[u8; 2]>,
+}
+
+impl<D> Printer<D> {
+    /// Consumes the printer and returns its driver, e.g. to close the transport or use it for
+    /// something else.
+    ///
+    /// Nothing is sent on the way out: the printer is neither reset nor cut, and bytes still
+    /// buffered by the driver are not flushed (see [`Printer::flush`]).
+    pub fn into_driver(self) -> D {
+        self.driver
+    }
+}
+
+impl Printer<Box<dyn Driver>> {
+    pub fn builder(transport: Transport) -> PrinterBuilder {
+        PrinterBuilder::new(transport)
+    }
+
+    pub fn usb(vid: u16, pid: u16, config: PrinterConfig) -> PrintyResult<Self> {
+        Self::builder(Transport::Usb { vid, pid })
+            .config(config)
+            .build()
+    }
+
+    pub fn debug(config: PrinterConfig) -> PrintyResult<Self> {
+        Self::builder(Transport::Debug).config(config).build()
+    }
+
+    /// Writes the command stream to `writer` instead of a printer, see [`WriterDriver`]
+    pub fn writer(writer: Box<dyn io::Write>, config: PrinterConfig) -> PrintyResult<Self> {
+        Self::builder(Transport::Writer(writer))
+            .config(config)
+            .build()
+    }
+
+    pub fn new(driver: Box<dyn Driver>, config: PrinterConfig) -> PrintyResult<Self> {
+        let mut printer = Printer {
+            driver,
+            config,
+            bytes_written: 0,
+            capabilities: None,
+            probed: false,
+            stored_graphics: HashSet::new(),
+        };
+        if !printer.config.skip_init {
+            printer.init()?;
+        }
+        Ok(printer)
+    }
+
+    fn init(&mut self) -> PrintyResult<&Self> {
+        /*
+          The printer (`TM-T88IV`) seems to transmit a 7-byte long data sequence (via the BULK endpoint)
+          upon powering on. This is not explicitly documented in the manual.
+
+          The sequence is as follows: [0x3B, 0x31, 0x0, 0x14, 0x0, 0x0, 0x0F]
+            - The first three bytes [0x3B, 0x31, 0x0] corresponds the closest to the transmitted
+              response of a power-off command (`DLE DC4 (fn=2)`).
+              - However, the identifier (`0x31`) does not match the expected value (`0x30`) according
+                to the specification.
+              - `TM-T88IV` does NOT support the power-off command (according to the `ESC/POS` manual),
+                which could a possible explanation for the mismatch.
+            - The remaining four bytes is the mysterious part, which seems to be a mixture of message
+              terminators and undocumented start-up/initialization sequences.
+
+          If the printer is powered on with an OFFLINE state (e.g. cover is open, paper is out), there
+          will be additional 4 bytes of `ASB` (Automatic Status Back) message (which seems to also
+          suggest that `ASB` is enabled by default).
+
+          As such, we will drain these initial bytes to avoid any issues with message backlogging
+          (transmitted data is only cleared after host reads it).
+        */
+        if self.config.drain_on_init {
+            self.driver.drain()?;
+        }
+        self.write(&self.config.init_sequence())?;
+        self.stored_graphics.clear();
+        Ok(self)
+    }
+
+    fn write(&mut self, data: &[u8]) -> PrintyResult<usize> {
+        debug!(
+            "Sending {} bytes ({}): {:02x?}",
+            data.len(),
+            self.driver.kind().name(),
+            &data[..data.len().min(LOG_CMD_MAX_LEN)]
+        );
+        let Some(interrupt) = self.config.interrupt.clone() else {
+            let w_len = self.driver.write(data)?;
+            self.bytes_written += w_len;
+            return Ok(w_len);
+        };
+
+        // Written piecewise, so that an interrupt stops a long write (e.g. a whole document)
+        // soon rather than once all of it was sent
+        let mut w_len = 0;
+        for chunk in data.chunks(INTERRUPT_CHECK_LEN) {
+            if interrupt.load(Ordering::Relaxed) {
+                return Err(PrintyError::Interrupted { written: w_len });
+            }
+            let chunk_w_len = self.driver.write(chunk).map_err(|e| match e {
+                PrintyError::PartialWrite { kind, written, .. } => PrintyError::PartialWrite {
+                    kind,
+                    expected: data.len(),
+                    written: w_len + written,
+                },
+                e => e,
+            })?;
+            w_len += chunk_w_len;
+            self.bytes_written += chunk_w_len;
+        }
+        Ok(w_len)
+    }
+
+    /// Abandons the job being printed after an interrupt: the data queued in the printer is
+    /// discarded (see [`Printer::clear_buffer_realtime`]) and the paper is cut, so that what
+    /// was printed tears off cleanly. [`PrinterConfig::interrupt`] is cleared, as nothing
+    /// could be sent otherwise.
+    pub fn abort_job(&mut self) -> PrintyResult<&mut Self> {
+        self.config.interrupt = None;
+        self.clear_buffer_realtime()?;
+        self.cut()
+    }
+
+    /// Total number of bytes sent to the driver since the printer was created, including
+    /// the initialization and control sequences (e.g. cut)
+    pub fn bytes_written(&self) -> usize {
+        self.bytes_written
+    }
+
+    /// Flushes the bytes buffered by the driver to the device, then discards any pending
+    /// input (e.g. unread status replies) if `drain_input` is set.
+    ///
+    /// Once this returns, everything written before it has been handed to the device, so that
+    /// replies read afterwards can only be to commands written afterwards (when draining).
+    pub fn flush(&mut self, drain_input: bool) -> PrintyResult<&mut Self> {
+        self.driver.flush()?;
+        if drain_input {
+            self.driver.drain()?;
+        }
+        Ok(self)
+    }
+
+    /// Queries the printer status, after flushing any buffered writes so that the status
+    /// reflects them
+    pub fn status(&mut self) -> Option<PrinterStatus> {
+        self.flush(false).ok()?;
+        let status = self.query_status();
+        if status.is_none() {
+            debug!("No valid status reply ({:?})", self.config.status_method);
+        }
+        status
+    }
+
+    fn query_status(&mut self) -> Option<PrinterStatus> {
+        let mut query = StatusQuery::new(&self.config);
+        while let Some((request, reply_delay)) = query.next_request() {
+            self.write(&request).ok()?;
+
+            sleep(reply_delay);
+
+            // Read into a buffer larger than the reply, as the transfer may also hold `ASB`
+            // messages
+            let mut buf = [0u8; RT_STATUS_BUF_LEN];
+            let len = self.driver.read(&mut buf).ok()?;
+            query.reply(&buf[..len])?;
+        }
+        query.status()
+    }
+
+    /// Returns whether pin 3 of the drawer kick-out connector is HIGH (via `GS r`)
+    pub fn drawer_status(&mut self) -> Option<bool> {
+        self.transmit_status(TransmitStatusReq::Drawer)
+            .and_then(drawer_pin_high_from_transmit_byte)
+    }
+
+    fn identity(&mut self, req: IdentityReq) -> Option<u8> {
+        self.write(&CMD_IDENTITY(req)).ok()?;
+
+        sleep(Duration::from_millis(CMD_PROC_DELAY_MS));
+
+        let mut buf = [0u8; 1];
+        match self.driver.read(&mut buf) {
+            Ok(len) if len == buf.len() => Some(buf[0]),
+            _ => None,
+        }
+    }
+
+    fn identity_string(&mut self, req: IdentityReq) -> Option<String> {
+        self.write(&CMD_IDENTITY(req)).ok()?;
+
+        sleep(Duration::from_millis(CMD_PROC_DELAY_MS));
+
+        // The reply may arrive over several reads, up to its terminator
+        let mut reply = Vec::new();
+        let mut buf = [0u8; IDENTITY_STRING_MAX_LEN + 2];
+        while reply.len() < buf.len() {
+            match self.driver.read(&mut buf) {
+                Ok(len) if len > 0 => {
+                    reply.extend_from_slice(&buf[..len]);
+                    if buf[..len].contains(&0) {
+                        break;
+                    }
+                }
+                _ => break,
+            }
+        }
+        parse_identity_string(&reply)
+    }
+
+    /// Firmware (ROM) version reported by the printer (via `GS I`), e.g. `"30.11 ESC/POS"`
+    pub fn firmware_version(&mut self) -> Option<String> {
+        self.identity_string(IdentityReq::Firmware)
+    }
+
+    /// Manufacturer name reported by the printer (via `GS I`)
+    pub fn maker(&mut self) -> Option<String> {
+        self.identity_string(IdentityReq::Maker)
+    }
+
+    /// Model name reported by the printer (via `GS I`), which unlike the model ID also names
+    /// models missing from the capabilities table
+    pub fn model_name(&mut self) -> Option<String> {
+        self.identity_string(IdentityReq::ModelName)
+    }
+
+    /// Identifies the printer model (via `GS I`) and looks up the features it supports.
+    ///
+    /// Once probed, methods relying on a feature the model does not support fail with
+    /// [`PrintyError::Unsupported`] instead of sending commands that the printer may mishandle.
+    pub fn probe_capabilities(&mut self) -> Option<Capabilities> {
+        self.probed = true;
+        let capabilities = Capabilities::from_model_id(self.identity(IdentityReq::Model)?);
+        self.capabilities = Some(capabilities.clone());
+        Some(capabilities)
+    }
+
+    /// Capabilities of the printer model, probed (see [`Printer::probe_capabilities`]) the
+    /// first time they are needed and cached from then on, even if the model could not be
+    /// identified. Drivers without a printer (see [`DriverKind::has_printer`]) are not probed,
+    /// as the query would only end up in their output.
+    pub fn capabilities(&mut self) -> Option<&Capabilities> {
+        if !self.probed && self.driver.kind().has_printer() {
+            self.probe_capabilities();
+        }
+        self.capabilities.as_ref()
+    }
+
+    /// Geometry of a line under the current config, e.g. to lay out content by column or dot.
+    /// It reflects changes to [`Printer::config`] (e.g. the columns or printable width).
+    pub fn metrics(&self) -> PrinterMetrics {
+        PrinterMetrics::from(&self.config)
+    }
+
+    /// Derives the number of Font A characters per line from the print width of the printer
+    /// model (see [`Printer::capabilities`]), or returns `None` if the model is unknown
+    pub fn detect_columns(&mut self) -> Option<usize> {
+        let print_width_dots = self.capabilities()?.print_width_dots()?;
+        Some((print_width_dots / FONT_A_WIDTH_DOTS) as usize)
+    }
+
+    /// Like [`Printer::detect_columns`], but for Font B characters
+    pub fn detect_font_b_columns(&mut self) -> Option<usize> {
+        let print_width_dots = self.capabilities()?.print_width_dots()?;
+        Some((print_width_dots / FONT_B_WIDTH_DOTS) as usize)
+    }
+
+    fn require(&self, feature: Feature) -> PrintyResult<()> {
+        match &self.capabilities {
+            Some(caps) if caps.supports(feature) == Some(false) => Err(PrintyError::Unsupported {
+                context: format!("{feature} is not supported by {}", caps.model_name()),
+            }),
+            // Unprobed or unknown models are given the benefit of the doubt
+            _ => Ok(()),
+        }
+    }
+
+    fn transmit_status(&mut self, req: TransmitStatusReq) -> Option<u8> {
+        self.write(&CMD_TRANSMIT_STATUS(req)).ok()?;
+
+        sleep(Duration::from_millis(CMD_PROC_DELAY_MS));
+
+        // Read into a buffer larger than the reply, as the transfer may also hold `ASB` messages
+        let mut buf = [0u8; RT_STATUS_BUF_LEN];
+        match self.driver.read(&mut buf) {
+            Ok(len) => transmit_status_byte(&buf[..len]),
+            _ => None,
+        }
+    }
+
+    /// Compiles markdown into the `ESC/POS` commands that [`Printer::print_md`] would send,
+    /// without sending them
+    pub fn compile_md(&self, md: &str) -> PrintyResult<Vec<u8>> {
+        EscposMarkdown::from(&self.config).compile(md)
+    }
+
+    /// Estimates the length of paper (in millimeters) that printing `compiled` would consume.
+    ///
+    /// This is an approximation: lines wrapped by the printer are not accounted for, and the
+    /// height of QR codes is inferred from the length of their data.
+    pub fn estimate_length(&self, compiled: &[u8]) -> f32 {
+        estimate_length_mm(compiled, self.config.line_height_mm)
+    }
+
+    /// Estimates the paper length, print time and size of `compiled`, e.g. to schedule batch
+    /// jobs. The time assumes the print speed of the model if it was probed (see
+    /// [`Printer::probe_capabilities`]), or [`DEFAULT_PRINT_SPEED_MM_S`] otherwise, and leaves
+    /// out the time taken to transfer the job and to cut.
+    pub fn estimate_job(&self, compiled: &[u8]) -> JobEstimate {
+        let length_mm = self.estimate_length(compiled);
+        let speed_mm_s = self
+            .capabilities
+            .as_ref()
+            .and_then(Capabilities::print_speed_mm_s)
+            .unwrap_or(DEFAULT_PRINT_SPEED_MM_S);
+        JobEstimate {
+            length_mm,
+            duration_s: length_mm / speed_mm_s,
+            speed_mm_s,
+            bytes: compiled.len(),
+        }
+    }
+
+    /// Prints a full-width line of `ch`, e.g. `-` or `=`, to separate sections of a receipt
+    pub fn separator(&mut self, ch: char) -> PrintyResult<&mut Self> {
+        // Anything beyond printable ASCII may span multiple columns (or bytes) on the printer
+        if !ch.is_ascii_graphic() {
+            return Err(PrintyError::Parse {
+                context: format!("Separator character {ch:?} is not a single-column character"),
+                source: None,
+            });
+        }
+
+        let columns = self.metrics().font_a_columns;
+        let line = format!("{}\n", ch.to_string().repeat(columns));
+        self.write(line.as_bytes())?;
+        Ok(self)
+    }
+
+    /// Sets the print density, from -6 (lightest) to 6 (darkest), see [`CMD_PRINT_DENSITY`].
+    ///
+    /// The setting is persisted in the printer's NV memory, which has limited write cycles, so
+    /// it should only be set when it needs changing. The printer resets to apply it, after which
+    /// it is initialized again.
+    pub fn set_density(&mut self, level: i8) -> PrintyResult<&mut Self> {
+        self.write(
+            &[
+                CMD_USER_SETTING_ENTER,
+                &CMD_PRINT_DENSITY(level),
+                CMD_USER_SETTING_EXIT,
+            ]
+            .concat(),
+        )?;
+        sleep(Duration::from_millis(CMD_PROC_DELAY_MS));
+        self.init()?;
+        Ok(self)
+    }
+
+    pub fn beep(&mut self, times: u8) -> PrintyResult<&mut Self> {
+        self.require(Feature::Buzzer)?;
+        // 200ms per beep
+        self.write(&CMD_BEEP(times, 4))?;
+        Ok(self)
+    }
+
+    /// Kicks the cash drawer connected to `pin` open (`ESC p`), once the data sent before is
+    /// printed
+    pub fn open_drawer(&mut self, pin: DrawerPin) -> PrintyResult<&mut Self> {
+        // 100ms on, then 100ms off
+        self.write(&CMD_PULSE(pin, 50, 50))?;
+        Ok(self)
+    }
+
+    /// Kicks the cash drawer connected to `pin` open right away (`DLE DC4 fn=1`). Unlike
+    /// [`Printer::open_drawer`], this skips the data queued in the printer, so it works while
+    /// the printer is busy or stalled.
+    pub fn open_drawer_realtime(&mut self, pin: DrawerPin) -> PrintyResult<&mut Self> {
+        // 100ms on, then 100ms off
+        self.write(&CMD_RT_GENERATE_PULSE(pin, 1))?;
+        self.flush(false)
+    }
+
+    /// Discards the data queued in the printer right away (`DLE DC4 fn=8`), including the rest
+    /// of a job that is being printed, returning whether the printer acknowledged it
+    pub fn clear_buffer_realtime(&mut self) -> PrintyResult<bool> {
+        self.write(CMD_RT_CLEAR_BUFFER)?;
+        self.flush(false)?;
+
+        sleep(Duration::from_millis(RT_STATUS_REPLY_DELAY_MS));
+
+        let mut buf = [0u8; RT_STATUS_BUF_LEN];
+        let len = self.driver.read(&mut buf)?;
+        Ok(buf[..len]
+            .windows(RT_CLEAR_BUFFER_REPLY.len())
+            .any(|reply| reply == RT_CLEAR_BUFFER_REPLY))
+    }
+
+    /// Prints `text` starting at column `col` (of Font A characters) of the current line, e.g. to
+    /// lay out item/price columns without padding with spaces. Fails for a column whose position
+    /// (in dots) cannot be encoded.
+    pub fn print_at_column(&mut self, col: u16, text: &str) -> PrintyResult<&mut Self> {
+        let position = col
+            .checked_mul(FONT_A_WIDTH_DOTS)
+            .ok_or_else(|| PrintyError::Parse {
+                context: format!("Column {col} is out of range"),
+                source: None,
+            })?;
+        let text = self.config.encode_text(&self.config.prepare_text(text));
+        self.write(&[CMD_ABS_POSITION(position), text].concat())?;
+        Ok(self)
+    }
+
+    /// Prints the characters 0x20-0xFF of code page `page` (`ESC t`) as a table, with the high
+    /// nibble labeling each row and the low nibble each column, to identify the code page the
+    /// printer uses. Code page 0 is selected afterwards, as after init.
+    pub fn codepage_table(&mut self, page: u8) -> PrintyResult<&mut Self> {
+        let mut table = format!("Code page {page}\n\n   ").into_bytes();
+        for low in 0..16u8 {
+            table.extend(format!(" {low:X}").as_bytes());
+        }
+        table.push(b'\n');
+        for high in 2..16u8 {
+            table.extend(format!("{high:X}x ").as_bytes());
+            for low in 0..16u8 {
+                table.extend([b' ', high << 4 | low]);
+            }
+            table.push(b'\n');
+        }
+
+        self.write(&[CMD_CODEPAGE(page), table, CMD_CODEPAGE(0)].concat())?;
+        Ok(self)
+    }
+
+    /// Feeds paper by `lines` lines (of the current line spacing)
+    pub fn feed(&mut self, lines: u8) -> PrintyResult<&mut Self> {
+        self.write(&CMD_FEED_LINES(lines))?;
+        Ok(self)
+    }
+
+    /// Prints a calibration ruler, e.g. to line up pre-printed forms: a horizontal scale across
+    /// the printable width, then a vertical scale fed [`RULER_FEED_MM`] down. Ticks are
+    /// [`RULER_TICK_MM`] apart, and every other one is longer and labeled in millimeters.
+    ///
+    /// Ticks are `|` characters centered on their position, so they are accurate to the dot
+    /// horizontally, and vertically to the feed pitch of the printer.
+    pub fn ruler(&mut self) -> PrintyResult<&mut Self> {
+        let max_width_dots = self.config.max_width_dots();
+        let width_mm = (max_width_dots as f32 / DOTS_PER_MM) as u16;
+        // Dot (horizontal motion unit) that a `width` dots wide string centered on `mm` starts at,
+        // kept within the printable width
+        let start_dot = |mm: u16, width: u16| {
+            ((mm as f32 * DOTS_PER_MM).round() as u16)
+                .saturating_sub(width / 2)
+                .min(max_width_dots.saturating_sub(width))
+        };
+
+        let ticks = (0..=width_mm).step_by(RULER_TICK_MM as usize);
+        let labeled = |mm: &u16| (mm / RULER_TICK_MM).is_multiple_of(2);
+        let mut ruler = CMD_JUSTIFY(JustifyReq::Left);
+        for tick in ticks.clone().filter(labeled) {
+            let label = tick.to_string();
+            let label_dots = label.len() as u16 * FONT_A_WIDTH_DOTS;
+            ruler.extend(CMD_ABS_POSITION(start_dot(tick, label_dots)));
+            ruler.extend(label.as_bytes());
+        }
+        ruler.push(b'\n');
+        // Labeled ticks span both lines, the others only the second
+        for is_long_row in [true, false] {
+            for tick in ticks.clone().filter(|mm| !is_long_row || labeled(mm)) {
+                ruler.extend(CMD_ABS_POSITION(start_dot(tick, FONT_A_WIDTH_DOTS)));
+                ruler.push(b'|');
+            }
+            ruler.push(b'\n');
+        }
+        ruler.push(b'\n');
+
+        // Each line is printed and fed by exactly one tick (`ESC J`), rather than by the line
+        // spacing
+        for mm in (0..=RULER_FEED_MM).step_by(RULER_TICK_MM as usize) {
+            let tick = if labeled(&mm) {
+                format!("-- {mm} mm")
+            } else {
+                "-".to_string()
+            };
+            ruler.extend(tick.as_bytes());
+            ruler.extend(CMD_FEED_MM(
+                RULER_TICK_MM as f32,
+                VERTICAL_MOTION_UNITS_PER_INCH,
+            ));
+        }
+        ruler.extend(CMD_JUSTIFY(self.config.align.into()));
+        self.write(&ruler)?;
+        Ok(self)
+    }
+
+    /// Prints `raster` (e.g. a logo) from the printer's RAM, where it is stored under `key` (two
+    /// printable ASCII characters) the first time, so that later prints only send the key.
+    ///
+    /// Graphics wider than the printable width (at the configured density) are scaled down to
+    /// fit. Stored graphics are assumed lost on init, after which they are stored again.
+    /// Storing different graphics under the same key requires a new `Printer`.
+    pub fn print_stored_graphics(
+        &mut self,
+        key: [u8; 2],
+        raster: &Raster,
+    ) -> PrintyResult<&mut Self> {
+        self.require(Feature::NvGraphics)?;
+        if !key.iter().all(|b| GRAPHICS_KEY_RANGE.contains(b)) {
+            return Err(PrintyError::Parse {
+                context: format!("Graphics key {key:?} must be two printable ASCII characters"),
+                source: None,
+            });
+        }
+
+        if !self.stored_graphics.contains(&key) {
+            let (x_scale, _) = self.config.image_density.scale();
+            let raster = raster.fit_width(self.config.max_width_dots() / x_scale as u16);
+            if raster.data().len() > GRAPHICS_MAX_DATA_LEN {
+                return Err(PrintyError::Parse {
+                    context: format!(
+                        "Graphics of {} bytes exceed the maximum of {GRAPHICS_MAX_DATA_LEN} bytes",
+                        raster.data().len()
+                    ),
+                    source: None,
+                });
+            }
+            self.write(&CMD_DEFINE_GRAPHICS(key, &raster))?;
+            self.stored_graphics.insert(key);
+        }
+        self.write(&CMD_PRINT_GRAPHICS(key, self.config.image_density))?;
+        Ok(self)
+    }
+
+    /// Turns the characters of the following text 90° clockwise (`ESC V`), e.g. for spine
+    /// labels. See [`CMD_ROTATE_90`] for how rotated text is laid out.
+    pub fn rotate_90(&mut self, enable: bool) -> PrintyResult<&mut Self> {
+        self.write(&CMD_ROTATE_90(enable))?;
+        Ok(self)
+    }
+
+    /// Aligns the following lines, from the start of the next line
+    pub fn align(&mut self, align: Align) -> PrintyResult<&mut Self> {
+        self.write(&CMD_JUSTIFY(align.into()))?;
+        Ok(self)
+    }
+
+    /// Prints `data` as a QR code, with modules `module_size` dots wide (1-16), see
+    /// [`CMD_QR_STORE`]. The code is encoded by the printer, on its own band of lines.
+    pub fn qr(
+        &mut self,
+        data: &str,
+        module_size: u8,
+        error_correction: QrErrorCorrection,
+    ) -> PrintyResult<&mut Self> {
+        self.require(Feature::Qr)?;
+        // The printer silently prints nothing for data that does not fit
+        let model = self.config.qr_model;
+        let max_data_len = model.max_data_len(error_correction);
+        if data.is_empty() || data.len() > max_data_len {
+            return Err(PrintyError::Parse {
+                context: format!(
+                    "QR code data must be 1-{max_data_len} bytes long for {model:?} at level \
+                     {error_correction}, got {}",
+                    data.len()
+                ),
+                source: None,
+            });
+        }
+
+        self.write(
+            &[
+                &CMD_QR_MODEL(model),
+                &CMD_QR_MODULE_SIZE(module_size),
+                &CMD_QR_ERROR_CORRECTION(error_correction),
+                &CMD_QR_STORE(data.as_bytes()),
+                CMD_QR_PRINT,
+            ]
+            .concat(),
+        )?;
+        Ok(self)
+    }
+
+    /// Prints a centered QR code of `data` with `caption` centered below it, e.g. the SSID and
+    /// password of a Wi-Fi network.
+    ///
+    /// Printing the caption beside the code would require rasterizing the text along with the
+    /// code into a single image, since the printer prints the code on its own band of lines.
+    pub fn qr_with_caption(&mut self, data: &str, caption: &str) -> PrintyResult<&mut Self> {
+        self.align(Align::Center)?
+            .qr(data, QR_CAPTION_MODULE_SIZE, QrErrorCorrection::M)?;
+        self.write(b"\n")?;
+        self.print(caption)?;
+        if !caption.trim().is_empty() && !caption.ends_with('\n') {
+            self.write(b"\n")?;
+        }
+        self.align(Align::Left)
+    }
+
+    /// Prints the digits `data` as a barcode of `symbology`, with the digits below it.
+    ///
+    /// If `data` is one digit short of the symbology's length (see [`Symbology::digits`]), its
+    /// check digit is computed and appended (see [`check_digit`]), as POS systems usually
+    /// store numbers without it. A given check digit is passed on as is.
+    pub fn barcode(&mut self, symbology: Symbology, data: &str) -> PrintyResult<&mut Self> {
+        let digits = symbology.digits();
+        if !data.bytes().all(|b| b.is_ascii_digit())
+            || (data.len() != digits && data.len() != digits - 1)
+        {
+            return Err(PrintyError::Parse {
+                context: format!(
+                    "{symbology:?} barcodes take {} or {digits} digits (with the check digit), \
+                     got {data:?}",
+                    digits - 1
+                ),
+                source: None,
+            });
+        }
+
+        let mut data = data.as_bytes().to_vec();
+        if data.len() == digits - 1 {
+            data.push(check_digit(&data));
+        }
+        self.write(&[CMD_BARCODE_HRI_BELOW, &CMD_BARCODE(symbology, &data)].concat())?;
+        Ok(self)
+    }
+
+    /// Cuts the paper. Nothing else cuts implicitly, only this and the `*_and_cut` methods
+    /// (e.g. [`Printer::print_and_cut`]) that call it.
+    pub fn cut(&mut self) -> PrintyResult<&mut Self> {
+        let cut_cmd = self.config.cut_sequence();
+        // Without a cut sequence, the cutter is never used
+        if !cut_cmd.is_empty() {
+            self.require(Feature::Cutter)?;
+        }
+        if self.config.feed_before_cut_mm > 0.0 {
+            self.write(&CMD_FEED_MM(
+                self.config.feed_before_cut_mm,
+                VERTICAL_MOTION_UNITS_PER_INCH,
+            ))?;
+        }
+        self.write(&cut_cmd)?;
+        Ok(self)
+    }
+
+    pub fn print(&mut self, data: &str) -> PrintyResult<&mut Self> {
+        if data.trim().is_empty() {
+            return Ok(self);
+        }
+
+        let text = self.config.prepare_text(data);
+        self.write(&self.config.encode_text(&text))?;
+        Ok(self)
+    }
+
+    pub fn print_md(&mut self, data: &str) -> PrintyResult<&mut Self> {
+        if data.trim().is_empty() {
+            return Ok(self);
+        }
+
+        self.write(&EscposMarkdown::from(&self.config).compile(data)?)?;
+        Ok(self)
+    }
+
+    /// Prints `text` highlighted in reverse video (`GS B`), or between shading blocks (`▒`) on
+    /// models without it. The blocks only print as such when transcoding into a code page that
+    /// has them (e.g. `CP437`).
+    pub fn highlight(&mut self, text: &str) -> PrintyResult<&mut Self> {
+        let text = self.config.prepare_text(text);
+        if self.require(Feature::Reverse).is_ok() {
+            let encoded = self.config.encode_text(&text);
+            self.write(&[CMD_REVERSE(true), encoded, CMD_REVERSE(false)].concat())?;
+        } else {
+            self.write(&self.config.encode_text(&format!("▒{text}▒")))?;
+        }
+        Ok(self)
+    }
+
+    /// Prints `text` in a heavier "shadow" style for legibility on faded paper, on models
+    /// whose bold is too faint: each line is printed, the paper is fed back by a dot (`ESC K`),
+    /// and the line is printed again one dot to the right.
+    ///
+    /// This requires a model that can feed paper backwards ([`Feature::ReverseFeed`]), so it
+    /// is probed first if it was not already. Unknown models are given the benefit of the
+    /// doubt. The offset is set by absolute positioning (`ESC $`), which only applies in left
+    /// justification, and lines longer than the paper is wide are wrapped out of register.
+    /// Markdown headings can be printed in this style with [`PrinterConfig::shadow_headings`].
+    pub fn print_shadow(&mut self, text: &str) -> PrintyResult<&mut Self> {
+        self.capabilities();
+        self.require(Feature::ReverseFeed)?;
+
+        let text = self.config.prepare_text(text);
+        for line in text.lines() {
+            let mut shadowed = overprint_shadow(&self.config.encode_text(line));
+            shadowed.push(b'\n');
+            self.write(&shadowed)?;
+        }
+        Ok(self)
+    }
+
+    /// Sends `data` as-is, e.g. an `ESC/POS` stream generated by another tool
+    pub fn print_raw(&mut self, data: &[u8]) -> PrintyResult<&mut Self> {
+        self.write(data)?;
+        Ok(self)
+    }
+
+    /// Sends `compiled` (e.g. from [`Printer::compile_md`]) from byte `offset` on, to resume a
+    /// job after a [`PrintyError::PartialWrite`] without reprinting what was already sent.
+    ///
+    /// Only the bytes are resent, so the printer must not have been reset in between, as it
+    /// would have lost the styles set before `offset`.
+    pub fn print_from_offset(&mut self, compiled: &[u8], offset: usize) -> PrintyResult<&mut Self> {
+        let remaining = compiled.get(offset..).ok_or(PrintyError::Parse {
+            context: format!(
+                "Offset {offset} is beyond the end of the job ({} bytes)",
+                compiled.len()
+            ),
+            source: None,
+        })?;
+        self.write(remaining)?;
+        Ok(self)
+    }
+
+    /// Prints the CRC-32 of `data` (the source of a job, e.g. markdown rather than the commands
+    /// it compiles to) as a footer line in the small font, so that a receipt can be reconciled
+    /// with the document it was printed from
+    pub fn print_hash_footer(&mut self, data: &str) -> PrintyResult<&mut Self> {
+        let line_break = if data.ends_with('\n') { "" } else { "\n" };
+        let footer = format!(
+            "{line_break}CRC32 {:08x}\n",
+            crc32fast::hash(data.as_bytes())
+        );
+        self.write(&[CMD_FONT_B(true), footer.into_bytes(), CMD_FONT_B(false)].concat())?;
+        Ok(self)
+    }
+
+    /// Prints `data` as a job and cuts, unless it is empty (or only whitespace): cutting
+    /// without printing anything would waste a blank slip
+    pub fn print_and_cut(&mut self, data: &str) -> PrintyResult<&mut Self> {
+        if data.trim().is_empty() {
+            return Ok(self);
+        }
+        self.start_job()?.print(data)?.finish_job(data)
+    }
+
+    /// Like [`Printer::print_and_cut`], for markdown
+    pub fn print_md_and_cut(&mut self, data: &str) -> PrintyResult<&mut Self> {
+        if data.trim().is_empty() {
+            return Ok(self);
+        }
+        self.start_job()?.print_md(data)?.finish_job(data)
+    }
+
+    /// Prints markdown like [`Printer::print_md`], but a few top-level blocks (e.g. paragraphs)
+    /// at a time, checking the status after every `check_every` blocks. This keeps a long job
+    /// from running on without paper.
+    ///
+    /// Printing stops before the next block when the printer is not ready (e.g. out of paper),
+    /// returning the offset of that block in the compiled commands (see
+    /// [`EscposMarkdown::compile_blocks`]). Pass that offset as `resume_offset` to resume the job
+    /// after reloading the paper, or 0 to print the whole job. Returns `None` once the
+    /// job is printed.
+    pub fn print_md_checked(
+        &mut self,
+        data: &str,
+        check_every: usize,
+        resume_offset: usize,
+    ) -> PrintyResult<Option<usize>> {
+        let (compiled, block_starts) = EscposMarkdown::from(&self.config).compile_blocks(data)?;
+        if resume_offset != 0 && !block_starts.contains(&resume_offset) {
+            return Err(PrintyError::Parse {
+                context: format!(
+                    "Offset {resume_offset} is not the start of a block of the job, was the \
+                     document changed?"
+                ),
+                source: None,
+            });
+        }
+
+        // The code page and alignment are set before the first block, and have to be set
+        // again when resuming on a freshly initialized printer
+        let preamble_len = block_starts.first().copied().unwrap_or(compiled.len());
+        let mut start = resume_offset.max(preamble_len);
+        self.write(&compiled[..preamble_len])?;
+
+        let block_starts: Vec<_> = block_starts.into_iter().filter(|&s| s > start).collect();
+        let mut warned_near_end = false;
+        for chunk_starts in block_starts.chunks(check_every.max(1)) {
+            let end = chunk_starts[chunk_starts.len() - 1];
+            self.write(&compiled[start..end])?;
+            start = end;
+
+            match self.status() {
+                Some(status) if !status.is_ready() => return Ok(Some(start)),
+                Some(status)
+                    if !warned_near_end && *status.paper_status() == PaperStatus::NearEnd =>
+                {
+                    warn!("Paper is running low, replace the roll soon");
+                    warned_near_end = true;
+                }
+                _ => {}
+            }
+        }
+        self.write(&compiled[start..])?;
+        Ok(None)
+    }
+
+    /// [`Printer::print_md_checked`], then cuts unless printing stopped. The job header is
+    /// only printed when starting from the beginning, not when resuming.
+    pub fn print_md_checked_and_cut(
+        &mut self,
+        data: &str,
+        check_every: usize,
+        resume_offset: usize,
+    ) -> PrintyResult<Option<usize>> {
+        if resume_offset == 0 {
+            self.start_job()?;
+        }
+        let stopped_at = self.print_md_checked(data, check_every, resume_offset)?;
+        if stopped_at.is_none() {
+            self.finish_job(data)?;
+        }
+        Ok(stopped_at)
+    }
+
+    /// Prints the job header, if configured
+    fn start_job(&mut self) -> PrintyResult<&mut Self> {
+        let header = self.config.job_header.clone();
+        self.write(&header)?;
+        Ok(self)
+    }
+
+    /// Cuts after a job printed from `data`, with the job footer and its hash footer if
+    /// configured
+    fn finish_job(&mut self, data: &str) -> PrintyResult<&mut Self> {
+        let footer = self.config.job_footer.clone();
+        self.write(&footer)?;
+        if self.config.footer_hash {
+            self.print_hash_footer(data)?;
+        }
+        self.cut()
+    }
+}
+
+#[derive(Clone)]
+pub struct EscposMarkdown {
+    columns: usize,
+    font_b_columns: usize,
+    bidi: bool,
+    preserve_lines: bool,
+    align: Align,
+    encoding: Encoding,
+    glyph_fallback: GlyphFallback,
+    glyph_font: Option<Arc<GlyphFont>>,
+    compact: bool,
+    italic_style: ItalicStyle,
+    strong_style: StrongStyle,
+    blockquote_style: BlockquoteStyle,
+    shadow_headings: bool,
+    image_density: ImageDensity,
+    image_base_dir: Option<PathBuf>,
+    max_width_dots: u16,
+    autolink: bool,
+    apply_front_matter: bool,
+}
+
+impl From<&PrinterConfig> for EscposMarkdown {
+    fn from(config: &PrinterConfig) -> Self {
+        Self {
+            columns: config.columns,
+            font_b_columns: config.font_b_columns,
+            bidi: config.bidi,
+            preserve_lines: config.preserve_lines,
+            align: config.align,
+            encoding: config.encoding,
+            glyph_fallback: config.glyph_fallback,
+            glyph_font: config.glyph_font.clone(),
+            compact: config.compact,
+            italic_style: config.italic_style,
+            strong_style: config.strong_style,
+            blockquote_style: config.blockquote_style,
+            shadow_headings: config.shadow_headings,
+            image_density: config.image_density,
+            image_base_dir: config.image_base_dir.clone(),
+            max_width_dots: config.max_width_dots(),
+            autolink: config.autolink,
+            apply_front_matter: config.apply_front_matter,
+        }
+    }
+}
+
+#[derive(Default)]
+struct CompileCtx<'a> {
+    footnote_defs: HashMap<&'a str, &'a mdast::FootnoteDefinition>,
+    // Link and image definitions (`[ref]: url`), that reference-style links and images resolve to
+    definitions: HashMap<&'a str, &'a mdast::Definition>,
+    // Identifiers of referenced footnotes, in order of first reference
+    footnote_refs: Vec<&'a str>,
+    // Alignments set by (possibly nested) `<!-- align:... -->` directives
+    align_stack: Vec<Align>,
+    // Code page that text is transcoded into, if any, until a `<!-- codepage:... -->` directive
+    // switches it
+    code_page: Option<CodePage>,
+    // Nesting depth of the list being compiled, 0 outside of lists
+    list_depth: usize,
+    // Nesting depth of the blockquote being compiled, 0 outside of blockquotes
+    quote_depth: u16,
+    // Offsets in the compiled commands that each top-level block starts at
+    block_starts: Vec<usize>,
+    // Whether the text being compiled is already underlined (e.g. by a heading), in which case
+    // URLs are not underlined again, as that would end the underline early
+    underlined: bool,
+}
+
+impl CompileCtx<'_> {
+    fn align(&self, default: Align) -> Align {
+        self.align_stack.last().copied().unwrap_or(default)
+    }
+}
+
+enum AlignDirective {
+    Start(Align),
+    End,
+}
+
+/// Returns the content of an HTML comment, which may be a directive (e.g. `align:center`)
+fn html_directive(html: &str) -> Option<&str> {
+    Some(
+        html.trim()
+            .strip_prefix("<!--")?
+            .strip_suffix("-->")?
+            .trim(),
+    )
+}
+
+/// Parses `<!-- codepage:cp437|cp850|cp1252 -->` comments, which switch the code page that the
+/// text after them is transcoded into (e.g. for sections in different languages)
+fn codepage_directive(html: &str) -> Option<CodePage> {
+    let page = html_directive(html)?.strip_prefix("codepage:")?.trim();
+    clap::ValueEnum::from_str(page, true).ok()
+}
+
+impl AlignDirective {
+    /// Parses `<!-- align:left|center|right -->` and `<!-- /align -->` comments
+    fn parse(html: &str) -> Option<Self> {
+        let directive = html_directive(html)?;
+        if directive == "/align" {
+            return Some(AlignDirective::End);
+        }
+        let align = directive.strip_prefix("align:")?.trim();
+        clap::ValueEnum::from_str(align, true)
+            .ok()
+            .map(AlignDirective::Start)
+    }
+}
 
 impl EscposMarkdown {
     pub fn compile(&self, md_str: &str) -> PrintyResult<Vec<u8>> {
-        let md_root_node = to_mdast(md_str, &markdown::ParseOptions::default()).map_err(|e| {
-            PrintyError::Parse {
-                context: format!("Failed to parse markdown - {e}"),
-                source: None,
+        self.compile_blocks(md_str).map(|(cmds, _)| cmds)
+    }
+
+    /// Compiles markdown like [`EscposMarkdown::compile`], also returning the offsets that
+    /// each top-level block (e.g. paragraph, list or table) starts at in the commands. Blocks
+    /// end in the state they started in, so a job can be split (or resumed) at any of them,
+    /// after the commands that precede the first block.
+    pub fn compile_blocks(&self, md_str: &str) -> PrintyResult<(Vec<u8>, Vec<usize>)> {
+        let (front_matter, md_str) = match front_matter::split(md_str) {
+            (Some(block), md_str) if self.apply_front_matter => {
+                (FrontMatter::parse(block)?, md_str)
             }
-        })?;
+            (_, md_str) => return self.compile_document(md_str),
+        };
+        Self {
+            align: front_matter.align.unwrap_or(self.align),
+            columns: front_matter.columns.unwrap_or(self.columns),
+            ..self.clone()
+        }
+        .compile_document(md_str)
+    }
+
+    fn compile_document(&self, md_str: &str) -> PrintyResult<(Vec<u8>, Vec<usize>)> {
+        let md_root_node = parse_md(md_str)?;
+
+        // First pass: gather footnote definitions so that references can be numbered and
+        // the definitions appended as endnotes, and link definitions so that reference-style
+        // links and images can be resolved
+        let mut ctx = CompileCtx {
+            code_page: self.encoding.code_page(md_str),
+            ..CompileCtx::default()
+        };
+        Self::collect_definitions(&md_root_node, &mut ctx);
 
         let mut compiled_cmds = Vec::<u8>::new();
-        self.compile_node(&md_root_node, &mut compiled_cmds);
-        Ok(compiled_cmds)
+        if let Some(page) = ctx.code_page {
+            compiled_cmds.extend(CMD_CODEPAGE(page.escpos_page()));
+        }
+        if self.align != Align::Left {
+            compiled_cmds.extend(CMD_JUSTIFY(self.align.into()));
+        }
+        self.compile_node(&md_root_node, &mut ctx, &mut compiled_cmds);
+        // Unclosed directives end with the document
+        if ctx.align(self.align) != self.align {
+            compiled_cmds.extend(CMD_JUSTIFY(self.align.into()));
+        }
+        self.compile_footnotes(&mut ctx, &mut compiled_cmds);
+        if self.align != Align::Left {
+            compiled_cmds.extend(CMD_JUSTIFY(JustifyReq::Left));
+        }
+        Ok((compiled_cmds, ctx.block_starts))
     }
 
-    fn compile_node(&self, node: &mdast::Node, buf: &mut Vec<u8>) {
+    // The first of several definitions with the same identifier wins, as in CommonMark
+    fn collect_definitions<'a>(node: &'a mdast::Node, ctx: &mut CompileCtx<'a>) {
         match node {
-            mdast::Node::Root(root) => root
-                .children
-                .iter()
-                .for_each(|child| self.compile_node(child, buf)),
+            mdast::Node::FootnoteDefinition(def) => {
+                ctx.footnote_defs.entry(&def.identifier).or_insert(def);
+            }
+            mdast::Node::Definition(def) => {
+                ctx.definitions.entry(&def.identifier).or_insert(def);
+            }
+            _ => {}
+        }
+        node.children()
+            .into_iter()
+            .flatten()
+            .for_each(|child| Self::collect_definitions(child, ctx));
+    }
+
+    /// Returns the URL of an inline image, or that of the definition a reference-style image
+    /// refers to
+    fn image_url<'a>(node: &'a mdast::Node, ctx: &CompileCtx<'a>) -> Option<&'a str> {
+        match node {
+            mdast::Node::Image(image) => Some(&image.url),
+            mdast::Node::ImageReference(image_ref) => ctx
+                .definitions
+                .get(image_ref.identifier.as_str())
+                .map(|def| def.url.as_str()),
+            _ => None,
+        }
+    }
+
+    fn compile_footnotes<'a>(&self, ctx: &mut CompileCtx<'a>, buf: &mut Vec<u8>) {
+        if ctx.footnote_refs.is_empty() {
+            return;
+        }
+
+        buf.extend(CMD_BOLD(true));
+        buf.extend_from_slice(b"Notes");
+        buf.extend(CMD_BOLD(false));
+        buf.extend_from_slice(self.block_separator());
+
+        // Definitions may reference other footnotes, which get appended while iterating
+        let mut idx = 0;
+        while let Some(&identifier) = ctx.footnote_refs.get(idx) {
+            idx += 1;
+            buf.extend(format!("[{idx}] ").as_bytes());
+            if let Some(def) = ctx.footnote_defs.get(identifier) {
+                def.children
+                    .iter()
+                    .for_each(|child| self.compile_node(child, ctx, buf));
+            }
+        }
+    }
+
+    /// Line feeds that end a block (e.g. a paragraph), leaving a blank line after it unless
+    /// compact
+    fn block_separator(&self) -> &'static [u8] {
+        if self.compact { b"\n" } else { b"\n\n" }
+    }
+
+    /// Prints a markdown image (binary PBM only) centered on its own band, scaled down to fit
+    /// the printable width. Returns `false` (with a warning) if it cannot be printed.
+    fn compile_image(&self, url: &str, ctx: &CompileCtx, buf: &mut Vec<u8>) -> bool {
+        let path = match &self.image_base_dir {
+            Some(dir) => dir.join(url),
+            None => PathBuf::from(url),
+        };
+        let raster = match std::fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|pbm| Raster::from_pbm(&pbm).map_err(|e| e.to_string()))
+        {
+            Ok(raster) => raster,
+            Err(e) => {
+                warn!("Skipping image {} - {e}", path.display());
+                return false;
+            }
+        };
+
+        let (x_scale, _) = self.image_density.scale();
+        let raster = raster.fit_width(self.max_width_dots / x_scale as u16);
+        if raster.data().len() > GRAPHICS_MAX_DATA_LEN {
+            warn!(
+                "Skipping image {}, whose {} bytes exceed the maximum of {GRAPHICS_MAX_DATA_LEN}",
+                path.display(),
+                raster.data().len()
+            );
+            return false;
+        }
+
+        buf.extend(CMD_JUSTIFY(JustifyReq::Center));
+        buf.extend(CMD_DEFINE_GRAPHICS(MD_IMAGE_GRAPHICS_KEY, &raster));
+        buf.extend(CMD_PRINT_GRAPHICS(
+            MD_IMAGE_GRAPHICS_KEY,
+            self.image_density,
+        ));
+        buf.extend(CMD_JUSTIFY(ctx.align(self.align).into()));
+        true
+    }
+
+    /// Appends `text`, transcoded into the code page of the document if any
+    fn compile_text(&self, text: &str, ctx: &CompileCtx, buf: &mut Vec<u8>) {
+        match ctx.code_page {
+            Some(page) => buf.extend(glyph::encode(
+                page,
+                text,
+                self.glyph_fallback,
+                self.glyph_font.as_deref(),
+            )),
+            None => buf.extend(text.as_bytes()),
+        }
+    }
+
+    /// Narrows the width magnification of a heading so that its text does not overflow the
+    /// line, as magnified text wraps unpredictably (especially when centered)
+    fn fit_width_magnify(&self, header: &mdast::Heading, w_magnify: u8) -> u8 {
+        let text_len = header
+            .children
+            .iter()
+            .map(|child| child.to_string().chars().count())
+            .sum::<usize>();
+        let max_w_magnify = (self.columns / text_len.max(1)).clamp(1, 8);
+        w_magnify.min(max_w_magnify as u8)
+    }
+
+    fn compile_node<'a>(&self, node: &'a mdast::Node, ctx: &mut CompileCtx<'a>, buf: &mut Vec<u8>) {
+        match node {
+            mdast::Node::Root(root) => {
+                let mut prev_end_line = None;
+                for child in &root.children {
+                    ctx.block_starts.push(buf.len());
+                    if self.preserve_lines
+                        && let (Some(prev_end_line), Some(pos)) = (prev_end_line, child.position())
+                    {
+                        // Blocks are already separated by a single blank line, unless compact
+                        let blank_lines = pos.start.line.saturating_sub(prev_end_line + 1);
+                        let separated_by = if self.compact { 0 } else { 1 };
+                        buf.extend(b"\n".repeat(blank_lines.saturating_sub(separated_by)));
+                    }
+                    self.compile_node(child, ctx, buf);
+                    prev_end_line = child.position().map(|pos| pos.end.line);
+                }
+            }
             mdast::Node::Paragraph(para) => {
+                // Images on their own are printed as a centered band, rather than within the text
+                if let Some(url) = sole_image(para).and_then(|image| Self::image_url(image, ctx))
+                    && self.compile_image(url, ctx, buf)
+                {
+                    return;
+                }
                 para.children
                     .iter()
-                    .for_each(|child| self.compile_node(child, buf));
-                buf.extend_from_slice(b"\n\n");
+                    .for_each(|child| self.compile_node(child, ctx, buf));
+                buf.extend_from_slice(self.block_separator());
             }
             mdast::Node::Heading(header) => {
                 let (style_cmds, reset_cmds) = match header.depth {
-                    1 => (CMD_CHAR_SIZE(1, 0).to_vec(), CMD_CHAR_SIZE(0, 0).to_vec()),
+                    1 => (
+                        CMD_CHAR_SIZE(2, self.fit_width_magnify(header, 2)),
+                        CMD_CHAR_SIZE(1, 1),
+                    ),
                     2 => (
                         [CMD_UNDERLINE(true), CMD_BOLD(true)].concat(),
                         [CMD_UNDERLINE(false), CMD_BOLD(false)].concat(),
@@ -403,22 +2843,903 @@ impl EscposMarkdown {
                     _ => (vec![], vec![]),
                 };
                 buf.extend_from_slice(&style_cmds);
+                let was_underlined = ctx.underlined;
+                ctx.underlined |= header.depth == 2;
+                let mut text = Vec::new();
                 header
                     .children
                     .iter()
-                    .for_each(|child| self.compile_node(child, buf));
+                    .for_each(|child| self.compile_node(child, ctx, &mut text));
+                ctx.underlined = was_underlined;
+                if self.shadow_headings && header.depth <= 3 {
+                    buf.extend(overprint_shadow(&text));
+                } else {
+                    buf.extend(text);
+                }
                 buf.extend_from_slice(&reset_cmds);
-                buf.extend_from_slice(b"\n\n");
+                buf.extend_from_slice(self.block_separator());
+            }
+            mdast::Node::Break(_) => buf.push(b'\n'),
+            mdast::Node::Text(text) => {
+                let text = if self.bidi {
+                    Cow::Owned(reorder_bidi(&text.value))
+                } else {
+                    Cow::Borrowed(text.value.as_str())
+                };
+                if !self.autolink || ctx.underlined {
+                    self.compile_text(&text, ctx, buf);
+                    return;
+                }
+                let mut end = 0;
+                for url in find_urls(&text) {
+                    self.compile_text(&text[end..url.start], ctx, buf);
+                    buf.extend(CMD_UNDERLINE(true));
+                    self.compile_text(&text[url.clone()], ctx, buf);
+                    buf.extend(CMD_UNDERLINE(false));
+                    end = url.end;
+                }
+                self.compile_text(&text[end..], ctx, buf);
+            }
+            mdast::Node::Strong(strong) => {
+                let style_cmd = match self.strong_style {
+                    StrongStyle::Bold => CMD_BOLD,
+                    StrongStyle::Reverse => CMD_REVERSE,
+                };
+                buf.extend(style_cmd(true));
+                strong
+                    .children
+                    .iter()
+                    .for_each(|child| self.compile_node(child, ctx, buf));
+                buf.extend(style_cmd(false));
+            }
+            mdast::Node::Emphasis(emphasis) => {
+                let (start, end) = match self.italic_style {
+                    ItalicStyle::Italic => (CMD_ITALIC(true).to_vec(), CMD_ITALIC(false).to_vec()),
+                    ItalicStyle::Underline => (CMD_UNDERLINE(true), CMD_UNDERLINE(false)),
+                };
+                buf.extend(start);
+                let was_underlined = ctx.underlined;
+                ctx.underlined |= self.italic_style == ItalicStyle::Underline;
+                emphasis
+                    .children
+                    .iter()
+                    .for_each(|child| self.compile_node(child, ctx, buf));
+                ctx.underlined = was_underlined;
+                buf.extend(end);
+            }
+            mdast::Node::Table(table) => {
+                // Absolute positioning only applies in left justification
+                let align = ctx.align(self.align);
+                if align != Align::Left {
+                    buf.extend(CMD_JUSTIFY(JustifyReq::Left));
+                }
+                for (row_idx, row) in table.children.iter().enumerate() {
+                    let cells = row.children().map_or(&[][..], |cells| cells.as_slice());
+                    // Cells are laid out in evenly sized columns (by absolute positioning), so
+                    // overlong cells overlap the next one
+                    let (columns, char_width) =
+                        if ctx.quote_depth > 0 && self.blockquote_style.is_small() {
+                            (self.font_b_columns, FONT_B_WIDTH_DOTS)
+                        } else {
+                            (self.columns, FONT_A_WIDTH_DOTS)
+                        };
+                    let cell_width = (columns / cells.len().max(1)) as u16;
+                    let is_header = row_idx == 0;
+
+                    if is_header {
+                        buf.extend(CMD_BOLD(true));
+                    }
+                    for (cell_idx, cell) in cells.iter().enumerate() {
+                        buf.extend(CMD_ABS_POSITION(cell_idx as u16 * cell_width * char_width));
+                        cell.children()
+                            .into_iter()
+                            .flatten()
+                            .for_each(|child| self.compile_node(child, ctx, buf));
+                    }
+                    if is_header {
+                        buf.extend(CMD_BOLD(false));
+                    }
+                    buf.push(b'\n');
+                }
+                if !self.compact {
+                    buf.push(b'\n');
+                }
+                if align != Align::Left {
+                    buf.extend(CMD_JUSTIFY(align.into()));
+                }
+            }
+            mdast::Node::List(list) => {
+                let indent = " ".repeat(LIST_INDENT * ctx.list_depth);
+                for (idx, item) in list.children.iter().enumerate() {
+                    let mdast::Node::ListItem(item) = item else {
+                        continue;
+                    };
+                    let marker = if list.ordered {
+                        format!("{}. ", list.start.unwrap_or(1) as usize + idx)
+                    } else {
+                        "- ".to_string()
+                    };
+                    let checkbox = match item.checked {
+                        Some(true) => "[x] ",
+                        Some(false) => "[ ] ",
+                        None => "",
+                    };
+                    buf.extend(format!("{indent}{marker}{checkbox}").as_bytes());
+
+                    // Paragraphs after the first are aligned with the text of the first one
+                    let continuation_indent = " ".repeat(indent.len() + marker.len());
+                    ctx.list_depth += 1;
+                    for (child_idx, child) in item.children.iter().enumerate() {
+                        match child {
+                            // Paragraphs in list items are kept to a single line feed
+                            mdast::Node::Paragraph(para) => {
+                                if child_idx > 0 {
+                                    buf.extend(continuation_indent.as_bytes());
+                                }
+                                para.children
+                                    .iter()
+                                    .for_each(|child| self.compile_node(child, ctx, buf));
+                                buf.push(b'\n');
+                            }
+                            _ => self.compile_node(child, ctx, buf),
+                        }
+                    }
+                    ctx.list_depth -= 1;
+                }
+                if ctx.list_depth == 0 && !self.compact {
+                    buf.push(b'\n');
+                }
             }
-            mdast::Node::Text(text) => buf.extend(text.value.as_bytes()),
-            mdast::Node::Strong(bold) => {
-                buf.extend(CMD_BOLD(true));
-                bold.children
+            // Blocks end with a line feed, so the margin always changes at the start of a line
+            mdast::Node::Blockquote(quote) => {
+                let style = self.blockquote_style;
+                if ctx.quote_depth == 0 && style.is_small() {
+                    buf.extend(CMD_FONT_B(true));
+                }
+                ctx.quote_depth += 1;
+                if style.indents() {
+                    buf.extend(CMD_LEFT_MARGIN(
+                        ctx.quote_depth * BLOCKQUOTE_INDENT * FONT_A_WIDTH_DOTS,
+                    ));
+                }
+                quote
+                    .children
                     .iter()
-                    .for_each(|child| self.compile_node(child, buf));
-                buf.extend(CMD_BOLD(false));
+                    .for_each(|child| self.compile_node(child, ctx, buf));
+                ctx.quote_depth -= 1;
+                if style.indents() {
+                    buf.extend(CMD_LEFT_MARGIN(
+                        ctx.quote_depth * BLOCKQUOTE_INDENT * FONT_A_WIDTH_DOTS,
+                    ));
+                }
+                if ctx.quote_depth == 0 && style.is_small() {
+                    buf.extend(CMD_FONT_B(false));
+                }
             }
+            mdast::Node::Html(html) => {
+                if let Some(page) = codepage_directive(&html.value) {
+                    ctx.code_page = Some(page);
+                    buf.extend(CMD_CODEPAGE(page.escpos_page()));
+                    return;
+                }
+                // Alignment changes take effect from the start of the next line
+                match AlignDirective::parse(&html.value) {
+                    Some(AlignDirective::Start(align)) => {
+                        ctx.align_stack.push(align);
+                        buf.extend(CMD_JUSTIFY(align.into()));
+                    }
+                    Some(AlignDirective::End) => {
+                        ctx.align_stack.pop();
+                        buf.extend(CMD_JUSTIFY(ctx.align(self.align).into()));
+                    }
+                    None => {}
+                }
+            }
+            mdast::Node::FootnoteReference(fn_ref) => {
+                let identifier = fn_ref.identifier.as_str();
+                if !ctx.footnote_defs.contains_key(identifier) {
+                    return;
+                }
+                let number = match ctx.footnote_refs.iter().position(|&id| id == identifier) {
+                    Some(pos) => pos + 1,
+                    None => {
+                        ctx.footnote_refs.push(identifier);
+                        ctx.footnote_refs.len()
+                    }
+                };
+                buf.extend(format!("[{number}]").as_bytes());
+            }
+            // Links print as their text, whether inline or by reference
+            mdast::Node::Link(mdast::Link { children, .. })
+            | mdast::Node::LinkReference(mdast::LinkReference { children, .. }) => children
+                .iter()
+                .for_each(|child| self.compile_node(child, ctx, buf)),
+            // Definitions are emitted as endnotes (see `compile_footnotes`)
+            mdast::Node::FootnoteDefinition(_) => {}
+            // Only resolved through the references to them
+            mdast::Node::Definition(_) => {}
             _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    use super::*;
+
+    // What a `MockDriver` was sent, and the replies it reads back in order (nothing once they
+    // run out)
+    #[derive(Default)]
+    struct Script {
+        written: Vec<u8>,
+        replies: VecDeque<Vec<u8>>,
+        // Poses as a real printer instead of the debug driver
+        usb: bool,
+    }
+
+    // Driver of a scripted printer, whose script stays readable once the driver is moved into
+    // a `Printer`
+    #[derive(Clone, Default)]
+    struct MockDriver(Rc<RefCell<Script>>);
+
+    impl MockDriver {
+        // Queues the bytes returned by the next read that has no reply queued yet
+        fn reply(&self, reply: &[u8]) -> &Self {
+            self.0.borrow_mut().replies.push_back(reply.to_vec());
+            self
+        }
+
+        fn written(&self) -> Vec<u8> {
+            self.0.borrow().written.clone()
+        }
+
+        fn as_usb(&self) -> &Self {
+            self.0.borrow_mut().usb = true;
+            self
+        }
+    }
+
+    impl Driver for MockDriver {
+        fn kind(&self) -> DriverKind {
+            if self.0.borrow().usb {
+                DriverKind::Usb
+            } else {
+                DriverKind::Debug
+            }
+        }
+
+        fn read(&mut self, buf: &mut [u8]) -> PrintyResult<usize> {
+            let Some(reply) = self.0.borrow_mut().replies.pop_front() else {
+                return Ok(0);
+            };
+            let len = reply.len().min(buf.len());
+            buf[..len].copy_from_slice(&reply[..len]);
+            Ok(len)
+        }
+
+        fn write(&mut self, data: &[u8]) -> PrintyResult<usize> {
+            self.0.borrow_mut().written.extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn drain(&mut self) -> PrintyResult<()> {
+            Ok(())
+        }
+    }
+
+    // A printer driven by a `MockDriver`, left uninitialized so that only what the test sends
+    // is written
+    fn mock_printer(config: PrinterConfig) -> (Printer<Box<dyn Driver>>, MockDriver) {
+        let driver = MockDriver::default();
+        let config = PrinterConfig {
+            skip_init: true,
+            ..config
+        };
+        let printer = Printer::new(Box::new(driver.clone()), config).unwrap();
+        (printer, driver)
+    }
+
+    #[test]
+    fn print_writes_crlf_as_lf() {
+        let (mut printer, driver) = mock_printer(PrinterConfig::default());
+        printer.print("one\r\ntwo\r\n").unwrap();
+        assert_eq!(driver.written(), b"one\ntwo\n");
+    }
+
+    #[test]
+    fn print_keeps_crlf_if_configured() {
+        let (mut printer, driver) = mock_printer(PrinterConfig {
+            keep_cr: true,
+            ..PrinterConfig::default()
+        });
+        printer.print("one\r\ntwo\r\n").unwrap();
+        assert_eq!(driver.written(), b"one\r\ntwo\r\n");
+    }
+
+    #[test]
+    fn print_skips_empty_content() {
+        let (mut printer, driver) = mock_printer(PrinterConfig::default());
+        printer.print("").unwrap().print(" \n\t").unwrap();
+        printer.print_md("\n\n").unwrap();
+        assert_eq!(driver.written(), b"");
+    }
+
+    #[test]
+    fn print_and_cut_does_not_cut_empty_content() {
+        let (mut printer, driver) = mock_printer(PrinterConfig::default());
+        printer
+            .print_and_cut("")
+            .unwrap()
+            .print_md_and_cut("  \n")
+            .unwrap();
+        assert_eq!(driver.written(), b"");
+
+        printer.print_and_cut("text").unwrap();
+        assert!(driver.written().ends_with(CMD_CUT));
+    }
+
+    #[test]
+    fn writer_driver_accepts_empty_writes() {
+        let mut driver = WriterDriver::new(Box::new(io::sink()));
+        assert_eq!(driver.write(&[]).unwrap(), 0);
+    }
+
+    #[test]
+    fn status_via_transmit_status() {
+        let (mut printer, driver) = mock_printer(PrinterConfig {
+            status_method: StatusMethod::GsR,
+            ..PrinterConfig::default()
+        });
+        driver.reply(&[0b0000_0011]);
+        let status = printer.status().unwrap();
+        assert_eq!(
+            driver.written(),
+            CMD_TRANSMIT_STATUS(TransmitStatusReq::PaperSensor)
+        );
+        assert!(status.is_online());
+        assert_eq!(*status.paper_status(), PaperStatus::NearEnd);
+    }
+
+    #[test]
+    fn drawer_status_via_transmit_status() {
+        let (mut printer, driver) = mock_printer(PrinterConfig::default());
+        driver.reply(&[0b0000_0001]).reply(&[0b0000_0000]);
+        assert_eq!(printer.drawer_status(), Some(true));
+        assert_eq!(printer.drawer_status(), Some(false));
+        assert_eq!(printer.drawer_status(), None);
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    }
+
+    // The first heading of a markdown document
+    fn heading(md: &str) -> mdast::Heading {
+        match parse_md(md).unwrap().children().unwrap().first() {
+            Some(mdast::Node::Heading(heading)) => heading.clone(),
+            node => panic!("expected a heading, got {node:?}"),
+        }
+    }
+
+    #[test]
+    fn heading_width_magnify_fits_columns() {
+        let compiler = EscposMarkdown::from(&PrinterConfig {
+            columns: 48,
+            ..PrinterConfig::default()
+        });
+        // 30 characters would take 60 columns at double width
+        let long = heading(&format!("# {}", "x".repeat(30)));
+        assert_eq!(compiler.fit_width_magnify(&long, 2), 1);
+        assert_eq!(compiler.fit_width_magnify(&long, 1), 1);
+
+        let short = heading("# Receipt");
+        assert_eq!(compiler.fit_width_magnify(&short, 2), 2);
+        assert_eq!(compiler.fit_width_magnify(&short, 8), 6);
+    }
+
+    #[test]
+    fn long_heading_stays_within_columns() {
+        let compiler = EscposMarkdown::from(&PrinterConfig {
+            columns: 48,
+            ..PrinterConfig::default()
+        });
+        // 30 characters at double width would take 60 of the 48 columns
+        let compiled = compiler.compile(&format!("# {}", "x".repeat(30))).unwrap();
+        assert!(contains(&compiled, &CMD_CHAR_SIZE(2, 1)));
+        assert!(!contains(&compiled, &CMD_CHAR_SIZE(2, 2)));
+
+        // 24 characters at double width take all 48
+        let compiled = compiler.compile(&format!("# {}", "x".repeat(24))).unwrap();
+        assert!(contains(&compiled, &CMD_CHAR_SIZE(2, 2)));
+    }
+
+    #[test]
+    fn print_at_column_positions_by_font_a_width() {
+        let (mut printer, driver) = mock_printer(PrinterConfig::default());
+        printer.print_at_column(30, "9.99").unwrap();
+        // 30 columns of 12 dots
+        assert_eq!(
+            driver.written(),
+            [&[0x1B, b'$', 0x68, 0x01][..], b"9.99"].concat()
+        );
+    }
+
+    #[test]
+    fn table_cells_are_positioned_absolutely() {
+        let compiler = EscposMarkdown::from(&PrinterConfig {
+            columns: 48,
+            ..PrinterConfig::default()
+        });
+        let compiled = compiler
+            .compile("| Item | Price |\n| --- | --- |\n| Tea | 3.50 |\n")
+            .unwrap();
+        let second_column = [CMD_ABS_POSITION(24 * FONT_A_WIDTH_DOTS), b"3.50".to_vec()].concat();
+        assert!(contains(&compiled, &second_column));
+    }
+
+    #[test]
+    fn compact_separates_paragraphs_by_single_line_feed() {
+        let md = "one\n\ntwo\n\n\n\n# three\n\nfour\n";
+        let compact = EscposMarkdown::from(&PrinterConfig {
+            compact: true,
+            ..PrinterConfig::default()
+        })
+        .compile(md)
+        .unwrap();
+        assert!(contains(&compact, b"one\ntwo\n"));
+        assert!(!contains(&compact, b"\n\n"));
+
+        let spaced = EscposMarkdown::from(&PrinterConfig::default())
+            .compile(md)
+            .unwrap();
+        assert!(contains(&spaced, b"one\n\ntwo\n\n"));
+    }
+
+    const OUT_EPT: u8 = 0x01;
+    const IN_EPT: u8 = 0x81;
+
+    fn retry_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            backoff: Duration::ZERO,
+        }
+    }
+
+    // Runs an I/O operation that fails with `errors` (in order) before succeeding, returning its
+    // result, the number of attempts and the number of times the halt was cleared
+    fn run_flaky_io(
+        policy: &RetryPolicy,
+        ept_addr: u8,
+        errors: &[rusb::Error],
+    ) -> (PrintyResult<usize>, usize, usize) {
+        let mut attempts = 0;
+        let mut halts_cleared = 0;
+        let result = io_with_retry(
+            policy,
+            ept_addr,
+            || {
+                attempts += 1;
+                errors.get(attempts - 1).map_or(Ok(42), |&e| Err(e))
+            },
+            || {
+                halts_cleared += 1;
+                Ok(())
+            },
+        );
+        (result, attempts, halts_cleared)
+    }
+
+    #[test]
+    fn io_retries_until_success() {
+        let errors = [rusb::Error::Pipe, rusb::Error::Timeout];
+        let (result, attempts, halts_cleared) = run_flaky_io(&retry_policy(3), OUT_EPT, &errors);
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(attempts, 3);
+        // Only the stall is cleared
+        assert_eq!(halts_cleared, 1);
+    }
+
+    #[test]
+    fn io_gives_up_after_max_attempts() {
+        let errors = [rusb::Error::Pipe; 3];
+        let (result, attempts, _) = run_flaky_io(&retry_policy(3), OUT_EPT, &errors);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::Io);
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn io_fails_fast_on_permanent_errors() {
+        for e in [rusb::Error::Access, rusb::Error::NoDevice] {
+            let (result, attempts, _) = run_flaky_io(&retry_policy(5), OUT_EPT, &[e]);
+            assert!(result.is_err());
+            assert_eq!(attempts, 1);
+        }
+    }
+
+    #[test]
+    fn io_does_not_retry_read_timeouts() {
+        let (result, attempts, _) = run_flaky_io(&retry_policy(5), IN_EPT, &[rusb::Error::Timeout]);
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn retry_backoff_doubles() {
+        let policy = RetryPolicy {
+            max_attempts: 4,
+            backoff: Duration::from_millis(100),
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+    }
+
+    #[test]
+    fn task_list_items_print_checkboxes() {
+        let compiled = EscposMarkdown::from(&PrinterConfig::default())
+            .compile("- [ ] fries\n- [x] burger\n- drink\n")
+            .unwrap();
+        assert!(contains(&compiled, b"- [ ] fries\n"));
+        assert!(contains(&compiled, b"- [x] burger\n"));
+        assert!(contains(&compiled, b"- drink\n"));
+    }
+
+    // Replies to `DLE EOT n=1-4` of an online printer with its drawer open and the paper
+    // running low
+    const NEAR_END_REPLIES: [u8; 4] = [0b0001_0110, 0b0001_0010, 0b0001_0010, 0b0001_1110];
+
+    #[test]
+    fn status_requests_each_dle_eot_separately() {
+        let (mut printer, driver) = mock_printer(PrinterConfig::default());
+        for reply in NEAR_END_REPLIES {
+            driver.reply(&[reply]);
+        }
+        let status = printer.status().unwrap();
+        assert!(status.is_online());
+        assert_eq!(*status.paper_status(), PaperStatus::NearEnd);
+
+        let requests: Vec<u8> = [1, 2, 3, 4]
+            .into_iter()
+            .flat_map(|n| [0x10, 0x04, n])
+            .collect();
+        assert_eq!(driver.written(), requests);
+    }
+
+    #[test]
+    fn status_skips_asb_interleaved_with_replies() {
+        let (mut printer, driver) = mock_printer(PrinterConfig::default());
+        // An `ASB` message arrives before the first reply, and another one after the third
+        let asb = [0b0001_0000, 0x00, 0x00, 0x00];
+        driver
+            .reply(&[&asb[..], &[NEAR_END_REPLIES[0]]].concat())
+            .reply(&[NEAR_END_REPLIES[1]])
+            .reply(&[&[NEAR_END_REPLIES[2]][..], &asb].concat())
+            .reply(&[NEAR_END_REPLIES[3]]);
+        let status = printer.status().unwrap();
+        assert!(status.is_online());
+        assert_eq!(*status.paper_status(), PaperStatus::NearEnd);
+    }
+
+    #[test]
+    fn status_is_unknown_without_reply() {
+        let (mut printer, driver) = mock_printer(PrinterConfig::default());
+        driver
+            .reply(&[NEAR_END_REPLIES[0]])
+            .reply(&[NEAR_END_REPLIES[1]]);
+        assert!(printer.status().is_none());
+    }
+
+    // Width (in dots) of the graphics defined by a `GS ( L` function 83 command at the start
+    // of `cmds`
+    fn defined_graphics_width(cmds: &[u8]) -> u16 {
+        assert!(cmds.starts_with(&[0x1D, b'(', b'L']) && cmds[5..7] == [48, 83]);
+        u16::from_le_bytes([cmds[11], cmds[12]])
+    }
+
+    #[test]
+    fn graphics_fit_half_the_width_at_double_width() {
+        let config = PrinterConfig {
+            max_width_mm: 48.0,
+            ..PrinterConfig::default()
+        };
+        let max_width_dots = config.max_width_dots();
+        let raster = Raster::new(600, 1, vec![0xFF; 75]).unwrap();
+
+        for (density, expected_width) in [
+            (ImageDensity::Normal, max_width_dots),
+            (ImageDensity::DoubleW, max_width_dots / 2),
+            (ImageDensity::DoubleH, max_width_dots),
+            (ImageDensity::Quad, max_width_dots / 2),
+        ] {
+            let (mut printer, driver) = mock_printer(PrinterConfig {
+                image_density: density,
+                ..config.clone()
+            });
+            printer.print_stored_graphics(*b"AB", &raster).unwrap();
+            let written = driver.written();
+            assert_eq!(defined_graphics_width(&written), expected_width);
+            assert!(written.ends_with(&CMD_PRINT_GRAPHICS(*b"AB", density)));
+        }
+    }
+
+    #[test]
+    fn status_ignores_near_end_if_configured() {
+        for (ignore_near_end, expected) in
+            [(false, PaperStatus::NearEnd), (true, PaperStatus::Adequate)]
+        {
+            let (mut printer, driver) = mock_printer(PrinterConfig {
+                ignore_near_end,
+                ..PrinterConfig::default()
+            });
+            for reply in NEAR_END_REPLIES {
+                driver.reply(&[reply]);
+            }
+            assert_eq!(*printer.status().unwrap().paper_status(), expected);
+        }
+    }
+
+    #[test]
+    fn cut_feeds_past_cutting_position_if_configured() {
+        let (mut printer, driver) = mock_printer(PrinterConfig::default());
+        printer.cut().unwrap();
+        assert_eq!(driver.written(), [0x1D, b'V', 66, 0]);
+
+        let (mut printer, driver) = mock_printer(PrinterConfig {
+            cut_feed_mm: Some(3.0),
+            ..PrinterConfig::default()
+        });
+        printer.cut().unwrap();
+        assert_eq!(driver.written(), [0x1D, b'V', 66, 43]);
+    }
+
+    #[test]
+    fn rotate_90_wraps_text() {
+        let (mut printer, driver) = mock_printer(PrinterConfig::default());
+        printer
+            .rotate_90(true)
+            .unwrap()
+            .print("SPINE\n")
+            .unwrap()
+            .rotate_90(false)
+            .unwrap();
+        assert_eq!(driver.written(), b"\x1bV\x01SPINE\n\x1bV\x00");
+    }
+
+    #[test]
+    fn status_reads_reply_batched_with_asb() {
+        let (mut printer, driver) = mock_printer(PrinterConfig::default());
+        // Each transfer is 8 bytes: the reply after stale ones, then an `ASB` message whose
+        // bytes look like replies
+        let asb = [0b0001_0000, 0b0001_0010, 0b0001_0010, 0b0001_0010];
+        for reply in NEAR_END_REPLIES {
+            driver.reply(&[&[0x12, 0x12, 0x12, reply][..], &asb].concat());
+        }
+        let status = printer.status().unwrap();
+        assert!(status.is_online());
+        assert_eq!(*status.paper_status(), PaperStatus::NearEnd);
+    }
+
+    #[test]
+    fn barcode_appends_missing_check_digit() {
+        let (mut printer, driver) = mock_printer(PrinterConfig::default());
+        printer.barcode(Symbology::Ean13, "400638133393").unwrap();
+        printer.barcode(Symbology::Ean13, "4006381333931").unwrap();
+        let barcode = [
+            CMD_BARCODE_HRI_BELOW,
+            &CMD_BARCODE(Symbology::Ean13, b"4006381333931"),
+        ]
+        .concat();
+        assert_eq!(driver.written(), barcode.repeat(2));
+    }
+
+    #[test]
+    fn barcode_rejects_wrong_digit_count() {
+        let (mut printer, driver) = mock_printer(PrinterConfig::default());
+        for data in ["40063813339", "40063813339310", "40063813339a"] {
+            let e = printer.barcode(Symbology::Ean13, data).err().unwrap();
+            assert_eq!(e.kind(), ErrorKind::Parse);
+        }
+        assert!(driver.written().is_empty());
+    }
+
+    fn position(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        haystack
+            .windows(needle.len())
+            .position(|window| window == needle)
+    }
+
+    #[test]
+    fn small_blockquote_switches_to_font_b() {
+        let md = "before\n\n> quoted\n\nafter\n";
+        for style in [BlockquoteStyle::Small, BlockquoteStyle::Both] {
+            let compiled = EscposMarkdown::from(&PrinterConfig {
+                blockquote_style: style,
+                ..PrinterConfig::default()
+            })
+            .compile(md)
+            .unwrap();
+            let font_b_on = position(&compiled, &CMD_FONT_B(true)).unwrap();
+            let font_b_off = position(&compiled, &CMD_FONT_B(false)).unwrap();
+            let quoted = position(&compiled, b"quoted").unwrap();
+            assert!(position(&compiled, b"before").unwrap() < font_b_on);
+            assert!(font_b_on < quoted && quoted < font_b_off);
+            assert!(font_b_off < position(&compiled, b"after").unwrap());
+        }
+
+        let indented = EscposMarkdown::from(&PrinterConfig::default())
+            .compile(md)
+            .unwrap();
+        assert!(!contains(&indented, &CMD_FONT_B(true)));
+    }
+
+    #[test]
+    fn reference_links_print_like_inline_links() {
+        let compiler = EscposMarkdown::from(&PrinterConfig::default());
+        let inline = compiler
+            .compile("See [the menu](https://a.example) and [hours](https://b.example).\n")
+            .unwrap();
+        let by_reference = compiler
+            .compile(
+                "See [the menu](https://a.example) and [hours][h].\n\n\
+                 [h]: https://b.example\n",
+            )
+            .unwrap();
+        assert_eq!(inline, by_reference);
+        assert!(contains(&inline, b"See the menu and hours."));
+    }
+
+    #[test]
+    fn reference_images_print_like_inline_images() {
+        let dir = std::env::temp_dir().join(format!("printy-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("logo.pbm"), b"P4\n8 2\n\xF0\x0F").unwrap();
+
+        let compiler = EscposMarkdown::from(&PrinterConfig {
+            image_base_dir: Some(dir.clone()),
+            ..PrinterConfig::default()
+        });
+        let inline = compiler.compile("![logo](logo.pbm)\n").unwrap();
+        let by_reference = compiler.compile("![logo][l]\n\n[l]: logo.pbm\n").unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(inline, by_reference);
+        assert!(contains(
+            &inline,
+            &CMD_PRINT_GRAPHICS(MD_IMAGE_GRAPHICS_KEY, ImageDensity::Normal)
+        ));
+    }
+
+    #[test]
+    fn capabilities_are_probed_once() {
+        let (mut printer, driver) = mock_printer(PrinterConfig::default());
+        driver.as_usb().reply(&[0x20]);
+        assert_eq!(printer.detect_columns(), Some(42));
+        assert_eq!(printer.detect_font_b_columns(), Some(56));
+        assert_eq!(driver.written(), CMD_IDENTITY(IdentityReq::Model));
+    }
+
+    #[test]
+    fn capabilities_are_cached_for_unknown_models() {
+        let (mut printer, driver) = mock_printer(PrinterConfig::default());
+        driver.as_usb();
+        assert!(printer.capabilities().is_none());
+        assert!(printer.capabilities().is_none());
+        assert_eq!(driver.written(), CMD_IDENTITY(IdentityReq::Model));
+    }
+
+    #[test]
+    fn capabilities_are_not_probed_without_a_printer() {
+        let (mut printer, driver) = mock_printer(PrinterConfig::default());
+        driver.reply(&[0x20]);
+        assert_eq!(printer.detect_columns(), None);
+        assert!(printer.capabilities().is_none());
+        assert_eq!(driver.written(), b"");
+    }
+
+    #[test]
+    fn print_shadow_overprints_each_line() {
+        let (mut printer, driver) = mock_printer(PrinterConfig::default());
+        printer.print_shadow("ab\ncd").unwrap();
+        let shadowed = |line: &[u8]| {
+            [
+                line,
+                &CMD_REVERSE_FEED_UNITS(1),
+                &CMD_ABS_POSITION(1),
+                line,
+                b"\n",
+            ]
+            .concat()
+        };
+        assert_eq!(
+            driver.written(),
+            [shadowed(b"ab"), shadowed(b"cd")].concat()
+        );
+    }
+
+    #[test]
+    fn shadow_headings_overprint_levels_1_to_3() {
+        let compiler = EscposMarkdown::from(&PrinterConfig {
+            shadow_headings: true,
+            ..PrinterConfig::default()
+        });
+        let shadow = [CMD_REVERSE_FEED_UNITS(1), CMD_ABS_POSITION(1)].concat();
+        let compiled = compiler.compile("### Total\n\n#### Note").unwrap();
+        assert!(contains(
+            &compiled,
+            &[b"Total", &shadow[..], b"Total"].concat()
+        ));
+        assert_eq!(compiled.windows(4).filter(|w| w == b"Note").count(), 1);
+
+        let compiled = EscposMarkdown::from(&PrinterConfig::default())
+            .compile("### Total")
+            .unwrap();
+        assert!(!contains(&compiled, &shadow));
+    }
+
+    #[test]
+    fn bidi_reorders_each_line_before_its_line_feed() {
+        let (mut printer, driver) = mock_printer(PrinterConfig {
+            bidi: true,
+            ..PrinterConfig::default()
+        });
+        printer.print("שלום עולם\nhello\n").unwrap();
+        assert_eq!(
+            String::from_utf8(driver.written()).unwrap(),
+            "םלוע םולש\nhello\n"
+        );
+
+        assert_eq!(
+            reorder_bidi("hello שלום\nעולם world\n\nשלום"),
+            "hello םולש\nworld םלוע\n\nםולש"
+        );
+    }
+
+    #[test]
+    fn usb_errors_are_classified_by_source() {
+        let usb_error = |e| PrintyError::Driver {
+            kind: DriverKind::Usb,
+            context: "Failed to open device".to_string(),
+            source: Some(Box::new(e)),
+        };
+        assert_eq!(
+            usb_error(rusb::Error::Access).kind(),
+            ErrorKind::AccessDenied
+        );
+        assert_eq!(
+            usb_error(rusb::Error::NoDevice).kind(),
+            ErrorKind::DeviceNotFound
+        );
+        assert_eq!(usb_error(rusb::Error::Busy).kind(), ErrorKind::Io);
+    }
+
+    #[test]
+    fn print_at_column_rejects_a_position_out_of_range() {
+        let (mut printer, driver) = mock_printer(PrinterConfig::default());
+        // 5462 columns of 12 dots overflow the 16-bit position
+        let e = printer.print_at_column(5462, "9.99").err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::Parse);
+        assert!(driver.written().is_empty());
+        printer.print_at_column(5461, "9.99").unwrap();
+    }
+
+    #[test]
+    fn codepage_directive_switches_encoding_of_following_text() {
+        let compiled = EscposMarkdown::from(&PrinterConfig::default())
+            .compile("Grüße\n\n<!-- codepage:cp850 -->\n\nGrüße Ø")
+            .unwrap();
+        // UTF-8 before the directive, CP850 after it
+        let switch = compiled
+            .windows(3)
+            .position(|w| w == CMD_CODEPAGE(2))
+            .unwrap();
+        assert!(contains(&compiled[..switch], "Grüße".as_bytes()));
+        assert!(contains(&compiled[switch..], b"Gr\x81\xE1e \x9D"));
+        assert!(!contains(&compiled[switch..], "ü".as_bytes()));
+    }
+}
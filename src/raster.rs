@@ -0,0 +1,137 @@
+//! Monochrome raster images, e.g. logos.
+
+use crate::printer::{PrintyError, PrintyResult};
+
+/// A monochrome image, packed as rows of 1 bit per dot (set bits are printed), most significant
+/// bit first. Each row is padded to a whole number of bytes.
+#[derive(Debug, Clone)]
+pub struct Raster {
+    width: u16,
+    height: u16,
+    data: Vec<u8>,
+}
+
+impl Raster {
+    pub fn new(width: u16, height: u16, data: Vec<u8>) -> PrintyResult<Self> {
+        let expected_len = width.div_ceil(8) as usize * height as usize;
+        if width == 0 || height == 0 || data.len() != expected_len {
+            return Err(PrintyError::Parse {
+                context: format!(
+                    "Raster of {width}x{height} dots needs {expected_len} bytes, got {}",
+                    data.len()
+                ),
+                source: None,
+            });
+        }
+        Ok(Self {
+            width,
+            height,
+            data,
+        })
+    }
+
+    /// Parses a binary (`P4`) PBM image, whose pixel data already is in the packed format
+    pub fn from_pbm(pbm: &[u8]) -> PrintyResult<Self> {
+        let parse_err = |context: &str| PrintyError::Parse {
+            context: format!("Invalid PBM image: {context}"),
+            source: None,
+        };
+
+        // The header is made of the magic number, width and height, separated by whitespace
+        // (and comments), followed by a single whitespace character
+        let mut fields = Vec::with_capacity(3);
+        let mut pos = 0;
+        while fields.len() < 3 {
+            match pbm.get(pos) {
+                Some(b'#') => {
+                    while pbm.get(pos).is_some_and(|&b| b != b'\n') {
+                        pos += 1;
+                    }
+                }
+                Some(b) if b.is_ascii_whitespace() => pos += 1,
+                Some(_) => {
+                    let start = pos;
+                    while pbm.get(pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+                        pos += 1;
+                    }
+                    fields.push(&pbm[start..pos]);
+                }
+                None => return Err(parse_err("truncated header")),
+            }
+        }
+        if fields[0] != b"P4" {
+            return Err(parse_err("not a binary (P4) PBM image"));
+        }
+        let dimension = |field: &[u8]| {
+            std::str::from_utf8(field)
+                .ok()
+                .and_then(|field| field.parse::<u16>().ok())
+                .ok_or(parse_err("invalid dimensions"))
+        };
+        let (width, height) = (dimension(fields[1])?, dimension(fields[2])?);
+
+        // Anything past the pixel data (e.g. a trailing newline) is ignored
+        let data = pbm.get(pos + 1..).unwrap_or_default();
+        let data_len = width.div_ceil(8) as usize * height as usize;
+        Self::new(width, height, data.get(..data_len).unwrap_or(data).to_vec())
+    }
+
+    /// Scales the image down (nearest neighbour) to at most `max_width` dots wide, keeping its
+    /// aspect ratio
+    pub fn fit_width(&self, max_width: u16) -> Raster {
+        if self.width <= max_width {
+            return self.clone();
+        }
+
+        let width = max_width.max(1);
+        let height = (self.height as u32 * width as u32 / self.width as u32).max(1) as u16;
+        let (row_len, src_row_len) = (width.div_ceil(8) as usize, self.width.div_ceil(8) as usize);
+        let mut data = vec![0u8; row_len * height as usize];
+        for y in 0..height as usize {
+            let src_y = y * self.height as usize / height as usize;
+            for x in 0..width as usize {
+                let src_x = x * self.width as usize / width as usize;
+                if self.data[src_y * src_row_len + src_x / 8] & (0x80 >> (src_x % 8)) != 0 {
+                    data[y * row_len + x / 8] |= 0x80 >> (x % 8);
+                }
+            }
+        }
+        Raster {
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// Inverts the image into a negative, e.g. for a logo on a black background. The padding
+    /// of each row is left unset.
+    pub fn invert(&self) -> Raster {
+        let row_len = self.width.div_ceil(8) as usize;
+        // Bits of the last byte of each row that are part of the image
+        let last_byte_mask = match self.width % 8 {
+            0 => 0xFF,
+            bits => 0xFF << (8 - bits),
+        };
+        let mut data: Vec<u8> = self.data.iter().map(|byte| !byte).collect();
+        for row in data.chunks_exact_mut(row_len) {
+            row[row_len - 1] &= last_byte_mask;
+        }
+        Raster {
+            width: self.width,
+            height: self.height,
+            data,
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+
+use crate::printer::{PrintyError, PrintyResult};
+
+/// Substitutes `{{key}}` placeholders (surrounding whitespace within the braces is ignored)
+/// with their values in `vars`.
+///
+/// Placeholders without a value are left verbatim, unless `strict` is set, in which case the
+/// first one found is reported as an error.
+pub fn render(
+    template: &str,
+    vars: &HashMap<String, String>,
+    strict: bool,
+) -> PrintyResult<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        let placeholder = &rest[start..start + 2 + len + 2];
+        let key = placeholder[2..placeholder.len() - 2].trim();
+
+        rendered.push_str(&rest[..start]);
+        match vars.get(key) {
+            Some(value) => rendered.push_str(value),
+            None if strict => {
+                return Err(PrintyError::Parse {
+                    context: format!("No value provided for placeholder {placeholder}"),
+                    source: None,
+                });
+            }
+            None => rendered.push_str(placeholder),
+        }
+        rest = &rest[start + placeholder.len()..];
+    }
+
+    rendered.push_str(rest);
+    Ok(rendered)
+}